@@ -706,7 +706,7 @@ fn parse_peer_seeds(seeds: &[String]) -> Vec<Peer> {
             pub_key,
             node_id,
             addr.into(),
-            PeerFlags::default(),
+            PeerFlags::SEED,
             PeerFeatures::COMMUNICATION_NODE,
             &[],
         );
@@ -452,13 +452,14 @@ impl<'a> DhtActor<'a> {
         list: &mut Vec<Peer>,
     ) -> Result<(), DhtActorError>
     {
+        let offline_expiry = peer_manager.offline_expiry().await?;
         let query = PeerQuery::new()
             .select_where(|peer| {
                 if peer.features != PeerFeatures::COMMUNICATION_CLIENT {
                     return false;
                 }
 
-                if peer.is_banned() || peer.is_offline() {
+                if peer.is_banned() || peer.is_offline_for_selection(offline_expiry) {
                     return false;
                 }
 
@@ -509,6 +510,7 @@ impl<'a> DhtActor<'a> {
         let mut banned_count = 0;
         let mut excluded_count = 0;
         let mut filtered_out_node_count = 0;
+        let offline_expiry = peer_manager.offline_expiry().await?;
         let query = PeerQuery::new()
             .select_where(|peer| {
                 if peer.is_banned() {
@@ -529,7 +531,7 @@ impl<'a> DhtActor<'a> {
                 }
 
                 let is_connect_eligible = {
-                    !peer.is_offline() &&
+                    !peer.is_offline_for_selection(offline_expiry) &&
                         // Check this peer was recently connectable
                         (peer.connection_stats.failed_attempts() <= config.broadcast_cooldown_max_attempts ||
                         peer.connection_stats
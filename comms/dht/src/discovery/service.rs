@@ -175,7 +175,7 @@ impl DhtDiscoveryService {
         use ConnectionManagerEvent::*;
         // The connection manager could not dial the peer on any address
         match event {
-            PeerConnectFailed(node_id, ConnectionManagerError::ConnectFailedMaximumAttemptsReached) => {
+            PeerConnectFailed(node_id, ConnectionManagerError::ConnectFailedMaximumAttemptsReached(_)) => {
                 if self.connection_manager.get_num_active_connections().await? == 0 {
                     info!(
                         target: LOG_TARGET,
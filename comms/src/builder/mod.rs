@@ -306,6 +306,9 @@ where
         let node_identity = self.node_identity.take().ok_or(CommsBuilderError::NodeIdentityNotSet)?;
 
         let peer_manager = self.make_peer_manager()?;
+        peer_manager
+            .set_local_identity(node_identity.public_key().clone())
+            .map_err(CommsBuilderError::PeerManagerError)?;
 
         //---------------------------------- Messaging --------------------------------------------//
 
@@ -52,7 +52,7 @@ use futures::{
     StreamExt,
 };
 use log::*;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, io, sync::Arc};
 use tari_crypto::tari_utilities::hex::Hex;
 use tari_shutdown::{Shutdown, ShutdownSignal};
 use tokio::time;
@@ -194,6 +194,22 @@ where
         let removed = self.cancel_signals.remove(&node_id);
         drop(removed);
 
+        // If every requester has stopped waiting for the dial, the connection is no longer wanted
+        if reply_tx.is_canceled() && !self.pending_dial_requests.contains_key(&node_id) {
+            if let Ok(conn) = &dial_result {
+                debug!(
+                    target: LOG_TARGET,
+                    "Disconnecting from peer '{}' because the dial was abandoned by the requester", peer_id_short_str
+                );
+                log_if_error!(
+                    target: LOG_TARGET,
+                    conn.clone().disconnect_silent().await,
+                    "Failed to disconnect abandoned connection because '{error}'",
+                );
+                return;
+            }
+        }
+
         match &dial_result {
             Ok(conn) => {
                 debug!(target: LOG_TARGET, "Successfully dialed peer '{}'", peer_id_short_str);
@@ -442,7 +458,7 @@ where
                         (state, Err(ConnectionManagerError::DialCancelled)) => break (state, Err(ConnectionManagerError::DialCancelled)),
                         (mut state, Err(err)) => {
                             if state.num_attempts() >= max_attempts {
-                                break (state, Err(ConnectionManagerError::ConnectFailedMaximumAttemptsReached));
+                                break (state, Err(ConnectionManagerError::ConnectFailedMaximumAttemptsReached(err.transport_error_kind())));
                             }
 
                             // Put the dial state and transport back for the retry
@@ -474,6 +490,7 @@ where
     {
         let mut addr_iter = dial_state.peer.addresses.address_iter();
         let cancel_signal = dial_state.get_cancel_signal();
+        let mut last_error_kind = None;
         loop {
             let result = match addr_iter.next() {
                 Some(address) => {
@@ -487,9 +504,9 @@ where
                     let dial_fut = async move {
                         let mut socket = transport
                             .dial(address.clone())
-                            .map_err(|err| ConnectionManagerError::TransportError(err.to_string()))?
+                            .map_err(Self::transport_dial_error)?
                             .await
-                            .map_err(|err| ConnectionManagerError::TransportError(err.to_string()))?;
+                            .map_err(Self::transport_dial_error)?;
                         debug!(
                             target: LOG_TARGET,
                             "Socket established on '{}'. Performing noise upgrade protocol", address
@@ -512,12 +529,13 @@ where
                         Either::Left((Err(err), _)) => {
                             debug!(
                                 target: LOG_TARGET,
-                                "(Attempt {}) Dial failed on address '{}' for peer '{}' because '{}'",
+                                "(Attempt {}) Dial failed on address '{}' for peer '{}' because '{:?}'",
                                 dial_state.num_attempts(),
                                 address,
                                 dial_state.peer.node_id.short_str(),
                                 err,
                             );
+                            last_error_kind = err.transport_error_kind();
                             // Try the next address
                             continue;
                         },
@@ -538,7 +556,7 @@ where
                     }
                 },
                 // No more addresses to try - returning failure
-                None => Err(ConnectionManagerError::DialConnectFailedAllAddresses),
+                None => Err(ConnectionManagerError::DialConnectFailedAllAddresses(last_error_kind)),
             };
 
             drop(addr_iter);
@@ -546,4 +564,14 @@ where
             break (dial_state, result);
         }
     }
+
+    /// Converts a transport error into a `ConnectionManagerError`. The `io::ErrorKind` is kept for transports that
+    /// fail with an `io::Error` so that the reason for the failure (e.g. refused or timed out) can be recorded.
+    fn transport_dial_error(err: TTransport::Error) -> ConnectionManagerError {
+        let err_ref: &(dyn std::error::Error + 'static) = &err;
+        match err_ref.downcast_ref::<io::Error>() {
+            Some(io_err) => ConnectionManagerError::TransportDialFailed(io_err.kind()),
+            None => ConnectionManagerError::TransportError(err.to_string()),
+        }
+    }
 }
@@ -22,11 +22,12 @@
 
 use crate::{
     noise,
-    peer_manager::PeerManagerError,
+    peer_manager::{ConnectionFailureReason, NodeId, PeerManagerError},
     protocol::{IdentityProtocolError, ProtocolError},
 };
 use derive_error::Error;
 use futures::channel::mpsc;
+use std::io;
 
 #[derive(Debug, Error, Clone)]
 pub enum ConnectionManagerError {
@@ -41,10 +42,17 @@ pub enum ConnectionManagerError {
     ActorRequestCanceled,
     /// The dial reply channel was closed when sending a reply
     DialReplyChannelClosed,
-    /// Failed to connect on all addresses for peer
-    DialConnectFailedAllAddresses,
-    /// Failed to connect to peer within the maximum number of attempts
-    ConnectFailedMaximumAttemptsReached,
+    /// Failed to connect on all addresses for peer. Contains the kind of transport error for the last address, if the
+    /// last address failed at the transport level.
+    #[error(no_from, non_std)]
+    DialConnectFailedAllAddresses(Option<io::ErrorKind>),
+    /// The peer does not have any addresses to dial
+    #[error(no_from, non_std)]
+    NoAddresses(NodeId),
+    /// Failed to connect to peer within the maximum number of attempts. Contains the kind of transport error for the
+    /// last attempt, if the last attempt failed at the transport level.
+    #[error(no_from, non_std)]
+    ConnectFailedMaximumAttemptsReached(Option<io::ErrorKind>),
     #[error(msg_embedded, no_from, non_std)]
     YamuxConnectionError(String),
     /// Establisher channel is closed or full
@@ -55,6 +63,9 @@ pub enum ConnectionManagerError {
     EstablisherChannelError,
     #[error(msg_embedded, no_from, non_std)]
     TransportError(String),
+    /// The transport failed to connect to an address
+    #[error(no_from, non_std)]
+    TransportDialFailed(io::ErrorKind),
     /// The peer authenticated to a public key which did not match the dialed peer's public key
     DialedPublicKeyMismatch,
     /// The noise transport failed to provide a valid static public key for the peer
@@ -80,6 +91,44 @@ pub enum ConnectionManagerError {
     WireFormatSendFailed,
 }
 
+impl ConnectionManagerError {
+    /// Returns the `io::ErrorKind` of the transport error that caused the dial to fail, if any
+    pub fn transport_error_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            ConnectionManagerError::TransportDialFailed(kind) => Some(*kind),
+            ConnectionManagerError::DialConnectFailedAllAddresses(kind) |
+            ConnectionManagerError::ConnectFailedMaximumAttemptsReached(kind) => *kind,
+            _ => None,
+        }
+    }
+
+    /// Returns the `ConnectionFailureReason` that best describes this error when it is the result of a failed dial
+    pub fn connection_failure_reason(&self) -> ConnectionFailureReason {
+        match self.transport_error_kind() {
+            Some(io::ErrorKind::TimedOut) => return ConnectionFailureReason::Timeout,
+            Some(io::ErrorKind::ConnectionRefused) => return ConnectionFailureReason::Refused,
+            _ => {},
+        }
+
+        match self {
+            ConnectionManagerError::DialConnectFailedAllAddresses(_) |
+            ConnectionManagerError::ConnectFailedMaximumAttemptsReached(_) |
+            ConnectionManagerError::TransportDialFailed(_) |
+            ConnectionManagerError::NoAddresses(_) => ConnectionFailureReason::Unreachable,
+            ConnectionManagerError::NoiseError(_) |
+            ConnectionManagerError::DialedPublicKeyMismatch |
+            ConnectionManagerError::InvalidStaticPublicKey |
+            ConnectionManagerError::IdentityProtocolError(_) |
+            ConnectionManagerError::PeerIdentityInvalidNodeId |
+            ConnectionManagerError::PeerIdentityNoValidAddresses |
+            ConnectionManagerError::YamuxUpgradeFailure(_) |
+            ConnectionManagerError::WireFormatSendFailed => ConnectionFailureReason::HandshakeFailed,
+            ConnectionManagerError::DialCancelled => ConnectionFailureReason::Cancelled,
+            _ => ConnectionFailureReason::Unknown,
+        }
+    }
+}
+
 impl From<yamux::ConnectionError> for ConnectionManagerError {
     fn from(err: yamux::ConnectionError) -> Self {
         ConnectionManagerError::YamuxConnectionError(err.to_string())
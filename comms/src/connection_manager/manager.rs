@@ -65,6 +65,9 @@ pub enum ConnectionManagerEvent {
     PeerDisconnected(Box<NodeId>),
     PeerConnectFailed(Box<NodeId>, ConnectionManagerError),
     PeerConnectWillClose(ConnId, Box<NodeId>, ConnectionDirection),
+    /// The peer closed the connection. This is handled by the connection manager and is published to subscribers as
+    /// `PeerDisconnected`.
+    PeerInitiatedDisconnect(ConnId, Box<NodeId>),
     PeerInboundConnectFailed(ConnectionManagerError),
 
     // Listener
@@ -89,6 +92,9 @@ impl fmt::Display for ConnectionManagerEvent {
                 node_id.short_str(),
                 direction
             ),
+            PeerInitiatedDisconnect(id, node_id) => {
+                write!(f, "PeerInitiatedDisconnect({}, {})", id, node_id.short_str())
+            },
             PeerInboundConnectFailed(err) => write!(f, "PeerInboundConnectFailed({:?})", err),
             Listening(addr) => write!(f, "Listening({})", addr),
             ListenFailed(err) => write!(f, "ListenFailed({:?})", err),
@@ -486,8 +492,32 @@ where
                     self.publish_event(PeerDisconnected(node_id));
                 }
             },
+            PeerInitiatedDisconnect(id, node_id) => {
+                // A connection that was replaced by another connection to the same peer (e.g. after a simultaneous
+                // dial) is not counted against the peer
+                let is_active_conn = self
+                    .active_connections
+                    .get(&node_id)
+                    .map(|conn| conn.id() == id)
+                    .unwrap_or(false);
+                if is_active_conn {
+                    if let Err(err) = self.peer_manager.record_peer_initiated_disconnect(&node_id).await {
+                        error!(
+                            target: LOG_TARGET,
+                            "record_peer_initiated_disconnect failed because '{:?}'", err
+                        );
+                    }
+                }
+                if self.active_connections.remove(&node_id).is_some() {
+                    self.publish_event(PeerDisconnected(node_id));
+                }
+            },
             PeerConnectFailed(node_id, err) => {
-                if let Err(err) = self.peer_manager.set_last_connect_failed(&node_id).await {
+                if let Err(err) = self
+                    .peer_manager
+                    .set_last_connect_failed_with_reason(&node_id, err.connection_failure_reason())
+                    .await
+                {
                     error!(target: LOG_TARGET, "set_peer_connect_failed failed because '{:?}'", err);
                 }
                 self.publish_event(PeerConnectFailed(node_id, err));
@@ -589,6 +619,16 @@ where
     )
     {
         match self.peer_manager.find_by_node_id(&node_id).await {
+            Ok(peer) if peer.addresses.is_empty() => {
+                debug!(target: LOG_TARGET, "Peer '{}' has no addresses to dial", node_id.short_str());
+                log_if_error_fmt!(
+                    level: warn,
+                    target: LOG_TARGET,
+                    reply_tx.send(Err(ConnectionManagerError::NoAddresses(node_id.clone()))),
+                    "Failed to send error reply when dialing peer '{}'",
+                    node_id.short_str()
+                );
+            },
             Ok(peer) => {
                 if let Err(err) = self.dialer_tx.send(DialerRequest::Dial(Box::new(peer), reply_tx)).await {
                     error!(target: LOG_TARGET, "Failed to send request to dialer because '{}'", err);
@@ -154,6 +154,11 @@ impl PeerConnection {
         self.started_at.elapsed()
     }
 
+    /// Returns the instant at which this connection was established
+    pub fn established_at(&self) -> Instant {
+        self.started_at
+    }
+
     pub fn reference_count(&self) -> usize {
         Arc::strong_count(&self.peer_node_id)
     }
@@ -265,7 +270,12 @@ impl PeerConnectionActor {
                         },
                         None => {
                             debug!(target: LOG_TARGET, "[{}] Peer '{}' closed the connection", self, self.peer_node_id.short_str());
-                            self.disconnect(false).await;
+                            self.disconnect(true).await;
+                            self.notify_event(ConnectionManagerEvent::PeerInitiatedDisconnect(
+                                self.id,
+                                Box::new(self.peer_node_id.clone()),
+                            ))
+                            .await;
                         },
                     }
                 }
@@ -445,4 +455,21 @@ mod test {
         drop(clone);
         assert_eq!(conn.reference_count(), 1);
     }
+
+    #[test]
+    fn established_at() {
+        let before = Instant::now();
+        let conn = PeerConnection::new(
+            1,
+            mpsc::channel(0).0,
+            Default::default(),
+            Multiaddr::empty(),
+            ConnectionDirection::Inbound,
+        );
+
+        assert!(conn.established_at() >= before);
+        assert!(conn.established_at() <= Instant::now());
+        assert_eq!(conn.clone().established_at(), conn.established_at());
+        assert_eq!(conn.direction(), ConnectionDirection::Inbound);
+    }
 }
@@ -26,13 +26,15 @@ use crate::{
         dialer::{Dialer, DialerRequest},
         listener::PeerListener,
         manager::ConnectionManagerEvent,
+        ConnectionDirection,
         ConnectionManagerConfig,
+        ConnectionManagerError,
     },
     noise::NoiseConfig,
-    peer_manager::{Peer, PeerFeatures, PeerFlags},
+    peer_manager::{ConnectionFailureReason, Peer, PeerFeatures, PeerFlags},
     protocol::ProtocolId,
     test_utils::{node_identity::build_node_identity, test_node::build_peer_manager},
-    transports::MemoryTransport,
+    transports::{MemoryTransport, TcpTransport},
 };
 use futures::{
     channel::{mpsc, oneshot},
@@ -42,7 +44,7 @@ use futures::{
     StreamExt,
 };
 use multiaddr::Protocol;
-use std::{error::Error, time::Duration};
+use std::{error::Error, io, net::TcpListener, time::Duration};
 use tari_shutdown::Shutdown;
 use tari_test_utils::unpack_enum;
 use tokio::{runtime::Handle, time::timeout};
@@ -193,3 +195,147 @@ async fn smoke() {
     timeout(Duration::from_secs(5), listener_fut).await.unwrap().unwrap();
     timeout(Duration::from_secs(5), dialer_fut).await.unwrap().unwrap();
 }
+
+#[tokio_macros::test_basic]
+async fn abandoned_dial_is_disconnected() {
+    let rt_handle = Handle::current();
+    let (event_tx, mut event_rx) = mpsc::channel(10);
+    let mut shutdown = Shutdown::new();
+
+    let node_identity1 = build_node_identity(PeerFeatures::COMMUNICATION_NODE);
+    let noise_config1 = NoiseConfig::new(node_identity1.clone());
+    let listener = PeerListener::new(
+        ConnectionManagerConfig {
+            listener_address: "/memory/0".parse().unwrap(),
+            ..Default::default()
+        },
+        MemoryTransport,
+        noise_config1,
+        event_tx.clone(),
+        build_peer_manager().into(),
+        node_identity1.clone(),
+        vec![],
+        shutdown.to_signal(),
+    );
+
+    let listener_fut = rt_handle.spawn(listener.run());
+
+    let node_identity2 = build_node_identity(PeerFeatures::COMMUNICATION_NODE);
+    let noise_config2 = NoiseConfig::new(node_identity2.clone());
+    let (mut request_tx, request_rx) = mpsc::channel(1);
+    let dialer = Dialer::new(
+        ConnectionManagerConfig::default(),
+        node_identity2.clone(),
+        build_peer_manager().into(),
+        MemoryTransport,
+        noise_config2,
+        ConstantBackoff::new(Duration::from_millis(100)),
+        request_rx,
+        event_tx,
+        vec![],
+        shutdown.to_signal(),
+    );
+
+    let dialer_fut = rt_handle.spawn(dialer.run());
+
+    let listen_event = event_rx.next().await.unwrap();
+    unpack_enum!(ConnectionManagerEvent::Listening(address) = listen_event);
+
+    let mut peer = Peer::new(
+        node_identity1.public_key().clone(),
+        node_identity1.node_id().clone(),
+        vec![address].into(),
+        PeerFlags::empty(),
+        PeerFeatures::COMMUNICATION_NODE,
+        &[],
+    );
+    peer.set_id_for_test(1);
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    request_tx
+        .send(DialerRequest::Dial(Box::new(peer), reply_tx))
+        .await
+        .unwrap();
+    // The requester stops waiting before the dial completes
+    drop(reply_rx);
+
+    // The listener reports the inbound connection and then its closure. The dialer does not report the outbound
+    // connection.
+    let mut events = Vec::new();
+    while let Ok(Some(event)) = timeout(Duration::from_secs(2), event_rx.next()).await {
+        events.push(event);
+    }
+    let num_outbound_connected = events
+        .iter()
+        .filter(|event| match event {
+            ConnectionManagerEvent::PeerConnected(conn) => conn.direction() == ConnectionDirection::Outbound,
+            _ => false,
+        })
+        .count();
+    assert_eq!(num_outbound_connected, 0);
+    assert!(events.iter().any(|event| match event {
+        ConnectionManagerEvent::PeerDisconnected(_) | ConnectionManagerEvent::PeerInitiatedDisconnect(_, _) => true,
+        _ => false,
+    }));
+
+    shutdown.trigger().unwrap();
+    timeout(Duration::from_secs(5), listener_fut).await.unwrap().unwrap();
+    timeout(Duration::from_secs(5), dialer_fut).await.unwrap().unwrap();
+}
+
+#[tokio_macros::test_basic]
+async fn refused_dial_reports_refused() {
+    let rt_handle = Handle::current();
+    let (event_tx, _event_rx) = mpsc::channel(10);
+    let mut shutdown = Shutdown::new();
+
+    // Bind to a free port and immediately close the listener so that connections to the port are refused
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let address = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+
+    let node_identity = build_node_identity(PeerFeatures::COMMUNICATION_NODE);
+    let noise_config = NoiseConfig::new(node_identity.clone());
+    let (mut request_tx, request_rx) = mpsc::channel(1);
+    let dialer = Dialer::new(
+        ConnectionManagerConfig {
+            max_dial_attempts: 1,
+            ..Default::default()
+        },
+        node_identity,
+        build_peer_manager().into(),
+        TcpTransport::new(),
+        noise_config,
+        ConstantBackoff::new(Duration::from_millis(100)),
+        request_rx,
+        event_tx,
+        vec![],
+        shutdown.to_signal(),
+    );
+
+    let dialer_fut = rt_handle.spawn(dialer.run());
+
+    let peer_identity = build_node_identity(PeerFeatures::COMMUNICATION_NODE);
+    let mut peer = Peer::new(
+        peer_identity.public_key().clone(),
+        peer_identity.node_id().clone(),
+        vec![address].into(),
+        PeerFlags::empty(),
+        PeerFeatures::COMMUNICATION_NODE,
+        &[],
+    );
+    peer.set_id_for_test(1);
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    request_tx
+        .send(DialerRequest::Dial(Box::new(peer), reply_tx))
+        .await
+        .unwrap();
+
+    let err = reply_rx.await.unwrap().unwrap_err();
+    assert_eq!(err.connection_failure_reason(), ConnectionFailureReason::Refused);
+    unpack_enum!(ConnectionManagerError::ConnectFailedMaximumAttemptsReached(kind) = err);
+    assert_eq!(kind, Some(io::ErrorKind::ConnectionRefused));
+
+    shutdown.trigger().unwrap();
+    timeout(Duration::from_secs(5), dialer_fut).await.unwrap().unwrap();
+}
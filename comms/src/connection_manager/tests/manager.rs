@@ -25,6 +25,7 @@ use crate::{
     connection_manager::{
         error::ConnectionManagerError,
         manager::ConnectionManagerEvent,
+        ConnectionDirection,
         ConnectionManager,
         ConnectionManagerRequester,
         PeerConnectionError,
@@ -84,6 +85,54 @@ async fn connect_to_nonexistent_peer() {
     shutdown.trigger().unwrap();
 }
 
+#[tokio_macros::test_basic]
+async fn dial_peer_without_addresses() {
+    let rt_handle = Handle::current();
+    let node_identity = build_node_identity(PeerFeatures::empty());
+    let noise_config = NoiseConfig::new(node_identity.clone());
+    let (request_tx, request_rx) = mpsc::channel(1);
+    let (event_tx, _) = broadcast::channel(1);
+    let mut requester = ConnectionManagerRequester::new(request_tx, event_tx.clone());
+    let mut shutdown = Shutdown::new();
+
+    let peer_manager = build_peer_manager();
+    let peer_identity = build_node_identity(PeerFeatures::COMMUNICATION_NODE);
+    peer_manager
+        .add_peer(Peer::new(
+            peer_identity.public_key().clone(),
+            peer_identity.node_id().clone(),
+            Default::default(),
+            PeerFlags::empty(),
+            PeerFeatures::COMMUNICATION_NODE,
+            &[],
+        ))
+        .await
+        .unwrap();
+    assert!(!peer_manager.has_addresses(peer_identity.node_id()).await.unwrap());
+
+    let connection_manager = ConnectionManager::new(
+        Default::default(),
+        MemoryTransport,
+        noise_config,
+        ConstantBackoff::new(Duration::from_secs(1)),
+        request_rx,
+        node_identity,
+        peer_manager,
+        Protocols::new(),
+        event_tx,
+        shutdown.to_signal(),
+    );
+
+    rt_handle.spawn(connection_manager.run());
+
+    let result = requester.dial_peer(peer_identity.node_id().clone()).await;
+    unpack_enum!(Result::Err(err) = result);
+    unpack_enum!(ConnectionManagerError::NoAddresses(node_id) = err);
+    assert_eq!(&node_id, peer_identity.node_id());
+
+    shutdown.trigger().unwrap();
+}
+
 #[tokio_macros::test_basic]
 async fn dial_success() {
     const TEST_PROTO: ProtocolId = ProtocolId::from_static(b"/test/valid");
@@ -172,6 +221,134 @@ async fn dial_success() {
     assert_eq!(buf, MSG);
 }
 
+#[tokio_macros::test_basic]
+async fn dial_reuses_inbound_connection() {
+    let mut shutdown = Shutdown::new();
+
+    let node_identity1 = build_node_identity(PeerFeatures::empty());
+    let node_identity2 = build_node_identity(PeerFeatures::empty());
+
+    let peer_manager1 = build_peer_manager();
+    let mut conn_man1 = build_connection_manager(
+        TestNodeConfig {
+            node_identity: node_identity1.clone(),
+            ..Default::default()
+        },
+        peer_manager1.clone(),
+        Protocols::new(),
+        shutdown.to_signal(),
+    );
+    conn_man1.wait_until_listening().await.unwrap();
+
+    let mut conn_man2 = build_connection_manager(
+        TestNodeConfig {
+            node_identity: node_identity2.clone(),
+            ..Default::default()
+        },
+        build_peer_manager(),
+        Protocols::new(),
+        shutdown.to_signal(),
+    );
+    let mut subscription2 = conn_man2.get_event_subscription();
+    let public_address2 = conn_man2.wait_until_listening().await.unwrap();
+
+    peer_manager1
+        .add_peer(Peer::new(
+            node_identity2.public_key().clone(),
+            node_identity2.node_id().clone(),
+            vec![public_address2].into(),
+            PeerFlags::empty(),
+            PeerFeatures::COMMUNICATION_CLIENT,
+            &[],
+        ))
+        .await
+        .unwrap();
+
+    conn_man1.dial_peer(node_identity2.node_id().clone()).await.unwrap();
+
+    let event = subscription2.next().await.unwrap().unwrap();
+    unpack_enum!(ConnectionManagerEvent::Listening(_addr) = &*event);
+    let event = subscription2.next().await.unwrap().unwrap();
+    unpack_enum!(ConnectionManagerEvent::PeerConnected(conn_in) = &*event);
+    assert_eq!(conn_in.direction(), ConnectionDirection::Inbound);
+
+    // The existing inbound connection is returned rather than dialing a new outbound connection
+    let conn = conn_man2.dial_peer(node_identity1.node_id().clone()).await.unwrap();
+    assert_eq!(conn.direction(), ConnectionDirection::Inbound);
+    assert_eq!(conn.id(), conn_in.id());
+    assert_eq!(conn_man2.get_num_active_connections().await.unwrap(), 1);
+
+    shutdown.trigger().unwrap();
+}
+
+#[tokio_macros::test_basic]
+async fn peer_initiated_disconnect_is_recorded() {
+    let mut shutdown = Shutdown::new();
+
+    let node_identity1 = build_node_identity(PeerFeatures::empty());
+    let node_identity2 = build_node_identity(PeerFeatures::empty());
+
+    let peer_manager1 = build_peer_manager();
+    let mut conn_man1 = build_connection_manager(
+        TestNodeConfig {
+            node_identity: node_identity1.clone(),
+            ..Default::default()
+        },
+        peer_manager1.clone(),
+        Protocols::new(),
+        shutdown.to_signal(),
+    );
+    conn_man1.wait_until_listening().await.unwrap();
+
+    let peer_manager2 = build_peer_manager();
+    let mut conn_man2 = build_connection_manager(
+        TestNodeConfig {
+            node_identity: node_identity2.clone(),
+            ..Default::default()
+        },
+        peer_manager2.clone(),
+        Protocols::new(),
+        shutdown.to_signal(),
+    );
+    let mut subscription2 = conn_man2.get_event_subscription();
+    let public_address2 = conn_man2.wait_until_listening().await.unwrap();
+
+    peer_manager1
+        .add_peer(Peer::new(
+            node_identity2.public_key().clone(),
+            node_identity2.node_id().clone(),
+            vec![public_address2].into(),
+            PeerFlags::empty(),
+            PeerFeatures::COMMUNICATION_CLIENT,
+            &[],
+        ))
+        .await
+        .unwrap();
+
+    let mut conn_out = conn_man1.dial_peer(node_identity2.node_id().clone()).await.unwrap();
+
+    let event = subscription2.next().await.unwrap().unwrap();
+    unpack_enum!(ConnectionManagerEvent::Listening(_addr) = &*event);
+    let event = subscription2.next().await.unwrap().unwrap();
+    unpack_enum!(ConnectionManagerEvent::PeerConnected(_conn_in) = &*event);
+
+    conn_out.disconnect().await.unwrap();
+
+    // Node 2 did not close the connection, so it records the disconnect against node 1
+    let event = subscription2.next().await.unwrap().unwrap();
+    unpack_enum!(ConnectionManagerEvent::PeerDisconnected(node_id) = &*event);
+    assert_eq!(&**node_id, node_identity1.node_id());
+    let peer1 = peer_manager2.find_by_node_id(node_identity1.node_id()).await.unwrap();
+    assert_eq!(peer1.connection_stats.peer_initiated_disconnects(), 1);
+    assert_eq!(conn_man2.get_num_active_connections().await.unwrap(), 0);
+
+    // Node 1 closed the connection itself, so nothing is recorded against node 2
+    let peer2 = peer_manager1.find_by_node_id(node_identity2.node_id()).await.unwrap();
+    assert_eq!(peer2.connection_stats.peer_initiated_disconnects(), 0);
+
+    shutdown.trigger().unwrap();
+}
+
 fn count_string_occurrences<T, U>(events: &[T], expected: &[&str]) -> usize
 where
     T: AsRef<U>,
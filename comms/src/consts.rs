@@ -25,6 +25,25 @@ use std::time::Duration;
 /// The maximum number of peers to return from the flood_identities method in peer manager
 pub const PEER_MANAGER_MAX_FLOOD_PEERS: usize = 1000;
 
+/// The number of peer store changes that are buffered for each change log subscriber
+pub const PEER_MANAGER_CHANGE_LOG_BUFFER_SIZE: usize = 100;
+
 /// The amount of time to consider a peer to be offline (i.e. dial to peer will fail without trying) after a failed
 /// connection attempt
 pub const PEER_OFFLINE_COOLDOWN_PERIOD: Duration = Duration::from_secs(60);
+
+/// The default amount of time after which a peer that is marked as offline is considered worth retrying (see
+/// `PeerManager::set_offline_expiry`)
+pub const PEER_OFFLINE_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The number of times in succession a peer may close its connection to this node before it is quarantined
+pub const PEER_DISCONNECT_QUARANTINE_THRESHOLD: usize = 5;
+
+/// The amount of time after the most recent peer-initiated disconnect that a quarantined peer is not dialed
+pub const PEER_DISCONNECT_QUARANTINE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// The amount of good behaviour after a ban that reduces a peer's ban count, used for ban escalation, by one
+pub const PEER_BAN_COUNT_DECAY_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The amount of time that a peer accepted as quarantined by the peer admission policy is not dialed
+pub const PEER_ADMISSION_QUARANTINE_PERIOD: Duration = Duration::from_secs(60 * 60);
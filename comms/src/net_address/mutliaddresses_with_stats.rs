@@ -176,6 +176,12 @@ impl MultiaddressesWithStats {
         self.addresses.sort();
     }
 
+    /// Discards all but the `n` most reliable addresses
+    pub fn retain_most_reliable(&mut self, n: usize) {
+        self.addresses.sort();
+        self.addresses.truncate(n);
+    }
+
     /// Returns the number of addresses
     pub fn len(&self) -> usize {
         self.addresses.len()
@@ -0,0 +1,47 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::types::CommsPublicKey;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A shareable record of a peer ban. Ban records can be exported from one node with `PeerManager::export_bans` and
+/// applied on another with `PeerManager::apply_ban_records`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BanRecord {
+    /// The public key of the banned peer
+    pub public_key: CommsPublicKey,
+    /// The remaining duration of the ban
+    pub duration: Duration,
+    /// The reason for the ban, if known
+    pub reason: Option<String>,
+}
+
+impl BanRecord {
+    pub fn new(public_key: CommsPublicKey, duration: Duration, reason: Option<String>) -> Self {
+        Self {
+            public_key,
+            duration,
+            reason,
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A compact, deterministic binary encoding for peer records. A record only contains the shareable fields of a peer:
+//! the public key, node id, addresses, features, flags and supported protocols. Local state, such as connection
+//! statistics and bans, is not encoded.
+//!
+//! A record starts with a version byte, followed by the fields in the order listed above. Lengths, counts and
+//! features are encoded as LEB128 varints.
+
+use crate::{
+    peer_manager::{NodeId, Peer, PeerFeatures, PeerFlags},
+    protocol::ProtocolId,
+    types::CommsPublicKey,
+};
+use derive_error::Error;
+use multiaddr::Multiaddr;
+use std::convert::TryFrom;
+use tari_crypto::tari_utilities::ByteArray;
+
+/// The current version of the peer record binary format
+pub const PEER_BINARY_FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum PeerBinaryFormatError {
+    /// The record ended unexpectedly
+    UnexpectedEof,
+    /// The record was encoded with an unsupported version of the binary format
+    #[error(no_from, non_std)]
+    UnsupportedVersion(u8),
+    /// The record contains an invalid length or varint
+    InvalidLength,
+    /// The record contains an invalid public key
+    InvalidPublicKey,
+    /// The record contains an invalid node id
+    InvalidNodeId,
+    /// The record contains an invalid address
+    InvalidAddress,
+    /// The record has unexpected trailing bytes
+    TrailingBytes,
+}
+
+impl Peer {
+    /// Encodes the shareable fields of the peer in the peer record binary format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.push(PEER_BINARY_FORMAT_VERSION);
+        write_bytes(&mut buf, self.public_key.as_bytes());
+        write_bytes(&mut buf, self.node_id.as_bytes());
+        write_varint(&mut buf, self.addresses.len() as u64);
+        for address in self.addresses.address_iter() {
+            write_bytes(&mut buf, &address.to_vec());
+        }
+        write_varint(&mut buf, self.features.bits());
+        buf.push(self.flags.bits());
+        write_varint(&mut buf, self.supported_protocols.len() as u64);
+        for protocol in &self.supported_protocols {
+            write_bytes(&mut buf, protocol);
+        }
+        buf
+    }
+
+    /// Decodes a peer from a record in the peer record binary format. Unknown feature and flag bits are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Peer, PeerBinaryFormatError> {
+        let mut reader = BinaryReader::new(bytes);
+        let version = reader.read_u8()?;
+        if version != PEER_BINARY_FORMAT_VERSION {
+            return Err(PeerBinaryFormatError::UnsupportedVersion(version));
+        }
+        let public_key =
+            CommsPublicKey::from_bytes(reader.read_bytes()?).map_err(|_| PeerBinaryFormatError::InvalidPublicKey)?;
+        let node_id = NodeId::from_bytes(reader.read_bytes()?).map_err(|_| PeerBinaryFormatError::InvalidNodeId)?;
+        let num_addresses = reader.read_len()?;
+        let mut addresses = Vec::with_capacity(num_addresses);
+        for _ in 0..num_addresses {
+            let address = Multiaddr::try_from(reader.read_bytes()?.to_vec())
+                .map_err(|_| PeerBinaryFormatError::InvalidAddress)?;
+            addresses.push(address);
+        }
+        let features = PeerFeatures::from_bits_truncate(reader.read_varint()?);
+        let flags = PeerFlags::from_bits_truncate(reader.read_u8()?);
+        let num_protocols = reader.read_len()?;
+        let mut supported_protocols = Vec::with_capacity(num_protocols);
+        for _ in 0..num_protocols {
+            supported_protocols.push(ProtocolId::copy_from_slice(reader.read_bytes()?));
+        }
+        if !reader.is_empty() {
+            return Err(PeerBinaryFormatError::TrailingBytes);
+        }
+
+        Ok(Peer::new(
+            public_key,
+            node_id,
+            addresses.into(),
+            flags,
+            features,
+            &supported_protocols,
+        ))
+    }
+}
+
+/// Writes `bytes` to `buf`, prefixed with its length
+pub(super) fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Reads values written in the peer record binary format from a byte slice
+pub(super) struct BinaryReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Reads a length prefixed byte slice, as written by `write_bytes`
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], PeerBinaryFormatError> {
+        let len = self.read_len()?;
+        if len > self.buf.len() {
+            return Err(PeerBinaryFormatError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PeerBinaryFormatError> {
+        let (byte, rest) = self.buf.split_first().ok_or(PeerBinaryFormatError::UnexpectedEof)?;
+        self.buf = rest;
+        Ok(*byte)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, PeerBinaryFormatError> {
+        let mut n = 0u64;
+        // A u64 varint is at most 10 bytes long
+        for i in 0..10 {
+            let byte = self.read_u8()?;
+            n |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(PeerBinaryFormatError::InvalidLength)
+    }
+
+    /// Reads a length or count. The length cannot be more than the number of remaining bytes, so that a corrupt
+    /// record cannot cause a large allocation.
+    fn read_len(&mut self) -> Result<usize, PeerBinaryFormatError> {
+        let len = self.read_varint()?;
+        if len > self.buf.len() as u64 {
+            return Err(PeerBinaryFormatError::UnexpectedEof);
+        }
+        Ok(len as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net_address::MultiaddressesWithStats;
+    use rand::rngs::OsRng;
+    use tari_crypto::{keys::PublicKey, tari_utilities::message_format::MessageFormat};
+
+    fn create_test_peer() -> Peer {
+        let (_sk, pk) = CommsPublicKey::random_keypair(&mut OsRng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let mut addresses = MultiaddressesWithStats::from("/ip4/1.2.3.4/tcp/8000".parse::<Multiaddr>().unwrap());
+        addresses.add_net_address(&"/ip6/::1/tcp/18000".parse::<Multiaddr>().unwrap());
+        Peer::new(
+            pk,
+            node_id,
+            addresses,
+            PeerFlags::SEED,
+            PeerFeatures::COMMUNICATION_NODE,
+            &[
+                ProtocolId::from_static(b"/tari/messaging/0.1.0"),
+                ProtocolId::from_static(b"/tari/identity/0.1.0"),
+            ],
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let peer = create_test_peer();
+        let bytes = peer.to_bytes();
+        assert_eq!(bytes[0], PEER_BINARY_FORMAT_VERSION);
+        assert!(bytes.len() < peer.to_json().unwrap().len() / 2);
+
+        let decoded = Peer::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.public_key, peer.public_key);
+        assert_eq!(decoded.node_id, peer.node_id);
+        assert_eq!(
+            decoded.addresses.address_iter().collect::<Vec<_>>(),
+            peer.addresses.address_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(decoded.features, peer.features);
+        assert_eq!(decoded.flags, peer.flags);
+        assert_eq!(decoded.supported_protocols, peer.supported_protocols);
+        // The encoding is deterministic
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = create_test_peer().to_bytes();
+        bytes[0] = PEER_BINARY_FORMAT_VERSION + 1;
+        assert_eq!(
+            Peer::from_bytes(&bytes).unwrap_err(),
+            PeerBinaryFormatError::UnsupportedVersion(PEER_BINARY_FORMAT_VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn malformed_records() {
+        assert_eq!(Peer::from_bytes(&[]).unwrap_err(), PeerBinaryFormatError::UnexpectedEof);
+
+        let bytes = create_test_peer().to_bytes();
+        assert_eq!(
+            Peer::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            PeerBinaryFormatError::UnexpectedEof
+        );
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(
+            Peer::from_bytes(&trailing).unwrap_err(),
+            PeerBinaryFormatError::TrailingBytes
+        );
+    }
+}
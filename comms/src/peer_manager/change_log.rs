@@ -0,0 +1,56 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::{NodeId, Peer, PeerFeatures};
+use std::time::Duration;
+
+/// A record of a single mutation made to the peer store
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStoreChange {
+    /// The node id of the peer that was changed
+    pub node_id: NodeId,
+    /// The kind of change that was made
+    pub kind: PeerStoreChangeKind,
+}
+
+impl PeerStoreChange {
+    pub fn new(node_id: NodeId, kind: PeerStoreChangeKind) -> Self {
+        Self { node_id, kind }
+    }
+}
+
+/// The kinds of changes that can be made to the peer store
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerStoreChangeKind {
+    /// The peer was added to the peer store, replacing any existing record. Contains the peer as stored.
+    Added(Box<Peer>),
+    /// The peer was updated. Contains the peer after the update was applied.
+    Updated(Box<Peer>),
+    /// The peer was banned for the given duration
+    Banned(Duration),
+    /// The peer was unbanned
+    Unbanned,
+    /// The features of the peer changed. Contains the new features.
+    FeaturesChanged(PeerFeatures),
+    /// The peer was deleted from the peer store
+    Deleted,
+}
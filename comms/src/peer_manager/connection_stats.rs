@@ -20,14 +20,19 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use crate::consts::PEER_DISCONNECT_QUARANTINE_PERIOD;
 use chrono::{NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::VecDeque,
     fmt,
     fmt::{Display, Formatter},
     time::Duration,
 };
 
+/// The number of most recent connection outcomes used to calculate the connection success rate
+pub const CONNECTION_OUTCOME_WINDOW_SIZE: usize = 10;
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct PeerConnectionStats {
     /// The last time a connection was successfully made or, None if a successful
@@ -35,6 +40,19 @@ pub struct PeerConnectionStats {
     pub last_connected_at: Option<NaiveDateTime>,
     /// Represents the last connection attempt
     pub last_connection_attempt: LastConnectionAttempt,
+    /// The outcomes (true for success) of the most recent connection attempts, oldest first
+    #[serde(default)]
+    recent_outcomes: VecDeque<bool>,
+    /// The reason the most recent failed connection attempt failed
+    #[serde(default)]
+    last_failure_reason: Option<ConnectionFailureReason>,
+    /// The number of times the peer has closed an established connection to this node, each within
+    /// `PEER_DISCONNECT_QUARANTINE_PERIOD` of the previous one
+    #[serde(default)]
+    peer_initiated_disconnects: usize,
+    /// The most recent time the peer closed an established connection to this node
+    #[serde(default)]
+    last_peer_initiated_disconnect_at: Option<NaiveDateTime>,
 }
 
 impl PeerConnectionStats {
@@ -46,14 +64,77 @@ impl PeerConnectionStats {
     pub fn set_connection_success(&mut self) {
         self.last_connected_at = Some(Utc::now().naive_utc());
         self.last_connection_attempt = LastConnectionAttempt::Succeeded(Utc::now().naive_utc());
+        self.record_outcome(true);
     }
 
-    /// Sets the last connection as a failure
+    /// Sets the last connection as a failure for an unknown reason
     pub fn set_connection_failed(&mut self) {
+        self.set_connection_failed_with_reason(ConnectionFailureReason::Unknown);
+    }
+
+    /// Sets the last connection as a failure for the given reason
+    pub fn set_connection_failed_with_reason(&mut self, reason: ConnectionFailureReason) {
         self.last_connection_attempt = LastConnectionAttempt::Failed {
             failed_at: Utc::now().naive_utc(),
             num_attempts: self.failed_attempts() + 1,
         };
+        self.last_failure_reason = Some(reason);
+        self.record_outcome(false);
+    }
+
+    /// Returns the reason that the most recent failed connection attempt failed, or None if a connection attempt has
+    /// never failed
+    pub fn last_failure_reason(&self) -> Option<ConnectionFailureReason> {
+        self.last_failure_reason
+    }
+
+    /// Returns the ratio of successful connections over the last `CONNECTION_OUTCOME_WINDOW_SIZE` connection attempts.
+    /// 0.0 is returned if no connection attempts have been recorded.
+    pub fn success_rate(&self) -> f32 {
+        if self.recent_outcomes.is_empty() {
+            return 0.0;
+        }
+        let num_succeeded = self.recent_outcomes.iter().filter(|succeeded| **succeeded).count();
+        num_succeeded as f32 / self.recent_outcomes.len() as f32
+    }
+
+    /// Returns the number of connection attempts used to calculate the success rate
+    pub fn num_recent_attempts(&self) -> usize {
+        self.recent_outcomes.len()
+    }
+
+    fn record_outcome(&mut self, is_success: bool) {
+        if self.recent_outcomes.len() == CONNECTION_OUTCOME_WINDOW_SIZE {
+            self.recent_outcomes.pop_front();
+        }
+        self.recent_outcomes.push_back(is_success);
+    }
+
+    /// Records that the peer closed an established connection to this node. A disconnect that happens more than
+    /// [PEER_DISCONNECT_QUARANTINE_PERIOD](crate::consts::PEER_DISCONNECT_QUARANTINE_PERIOD) after the previous one
+    /// starts a new count.
+    pub fn record_peer_initiated_disconnect(&mut self) {
+        self.peer_initiated_disconnects = self.peer_initiated_disconnects() + 1;
+        self.last_peer_initiated_disconnect_at = Some(Utc::now().naive_utc());
+    }
+
+    /// Returns the number of times in succession that the peer has closed an established connection to this node,
+    /// where each disconnect happened within
+    /// [PEER_DISCONNECT_QUARANTINE_PERIOD](crate::consts::PEER_DISCONNECT_QUARANTINE_PERIOD) of the previous one. 0 is
+    /// returned once that period has elapsed since the most recent disconnect.
+    pub fn peer_initiated_disconnects(&self) -> usize {
+        match self.time_since_last_peer_initiated_disconnect() {
+            Some(since) if since <= PEER_DISCONNECT_QUARANTINE_PERIOD => self.peer_initiated_disconnects,
+            _ => 0,
+        }
+    }
+
+    /// Returns the Duration since the peer last closed an established connection to this node. None is returned if
+    /// this has never happened.
+    pub fn time_since_last_peer_initiated_disconnect(&self) -> Option<Duration> {
+        self.last_peer_initiated_disconnect_at
+            .map(|dt| Utc::now().naive_utc() - dt)
+            .map(convert_to_std_duration)
     }
 
     /// Returns true if a successful connection has ever been recorded, otherwise false
@@ -92,6 +173,9 @@ impl fmt::Display for PeerConnectionStats {
         match self.last_failed_at() {
             Some(_) => {
                 write!(f, "{}", self.last_connection_attempt)?;
+                if let Some(reason) = self.last_failure_reason {
+                    write!(f, " because the {}", reason)?;
+                }
             },
             None => match self.last_connected_at.as_ref() {
                 Some(dt) => {
@@ -123,6 +207,37 @@ pub enum LastConnectionAttempt {
     },
 }
 
+/// The reason that a connection attempt failed
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ConnectionFailureReason {
+    /// The reason for the failure is not known
+    Unknown,
+    /// The peer could not be reached on any of its addresses
+    Unreachable,
+    /// The connection attempt timed out
+    Timeout,
+    /// The peer refused the connection
+    Refused,
+    /// A connection was made but the handshake with the peer failed
+    HandshakeFailed,
+    /// The connection attempt was cancelled
+    Cancelled,
+}
+
+impl Display for ConnectionFailureReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        use ConnectionFailureReason::*;
+        match self {
+            Unknown => write!(f, "reason is unknown"),
+            Unreachable => write!(f, "peer is unreachable"),
+            Timeout => write!(f, "connection timed out"),
+            Refused => write!(f, "connection was refused"),
+            HandshakeFailed => write!(f, "handshake failed"),
+            Cancelled => write!(f, "connection attempt was cancelled"),
+        }
+    }
+}
+
 /// Convert `chrono::Duration` to `std::time::Duration`
 fn convert_to_std_duration(old_duration: chrono::Duration) -> Duration {
     Duration::from_millis(old_duration.num_milliseconds() as u64)
@@ -184,4 +299,66 @@ mod test {
         state.set_connection_success();
         assert_eq!(state.has_ever_connected(), true);
     }
+
+    #[test]
+    fn last_failure_reason() {
+        let mut state = PeerConnectionStats::new();
+        assert!(state.last_failure_reason().is_none());
+
+        state.set_connection_failed();
+        assert_eq!(state.last_failure_reason(), Some(ConnectionFailureReason::Unknown));
+
+        state.set_connection_failed_with_reason(ConnectionFailureReason::Refused);
+        assert_eq!(state.last_failure_reason(), Some(ConnectionFailureReason::Refused));
+        assert_eq!(state.failed_attempts(), 2);
+        assert!(state.to_string().ends_with("because the connection was refused"));
+    }
+
+    #[test]
+    fn peer_initiated_disconnects() {
+        let mut state = PeerConnectionStats::new();
+        assert_eq!(state.peer_initiated_disconnects(), 0);
+        assert!(state.time_since_last_peer_initiated_disconnect().is_none());
+
+        state.record_peer_initiated_disconnect();
+        state.record_peer_initiated_disconnect();
+        assert_eq!(state.peer_initiated_disconnects(), 2);
+        assert!(state.time_since_last_peer_initiated_disconnect().unwrap() < Duration::from_secs(60));
+
+        // Disconnects that are older than the quarantine period are no longer counted
+        let period = chrono::Duration::from_std(PEER_DISCONNECT_QUARANTINE_PERIOD).unwrap();
+        state.last_peer_initiated_disconnect_at = Some(Utc::now().naive_utc() - period - chrono::Duration::seconds(1));
+        assert_eq!(state.peer_initiated_disconnects(), 0);
+        state.record_peer_initiated_disconnect();
+        assert_eq!(state.peer_initiated_disconnects(), 1);
+    }
+
+    #[test]
+    fn success_rate() {
+        let mut state = PeerConnectionStats::new();
+        assert!(state.success_rate().abs() < std::f32::EPSILON);
+        assert_eq!(state.num_recent_attempts(), 0);
+
+        state.set_connection_success();
+        state.set_connection_failed();
+        assert!((state.success_rate() - 0.5).abs() < std::f32::EPSILON);
+
+        // Fill the window with failures so that the earlier success ages out
+        for _ in 0..CONNECTION_OUTCOME_WINDOW_SIZE - 1 {
+            state.set_connection_failed();
+        }
+        assert_eq!(state.num_recent_attempts(), CONNECTION_OUTCOME_WINDOW_SIZE);
+        assert!(state.success_rate().abs() < std::f32::EPSILON);
+
+        for _ in 0..CONNECTION_OUTCOME_WINDOW_SIZE / 2 {
+            state.set_connection_success();
+        }
+        assert!((state.success_rate() - 0.5).abs() < std::f32::EPSILON);
+
+        for _ in 0..CONNECTION_OUTCOME_WINDOW_SIZE {
+            state.set_connection_success();
+        }
+        assert_eq!(state.num_recent_attempts(), CONNECTION_OUTCOME_WINDOW_SIZE);
+        assert!((state.success_rate() - 1.0).abs() < std::f32::EPSILON);
+    }
 }
@@ -0,0 +1,43 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::node_id::{NodeDistance, NodeId};
+
+/// A metric used to calculate the distance between two node ids. The peer manager uses the metric to determine the
+/// closest peers to a node id and the extent of a network region.
+///
+/// The default metric is `XorDistance`. Alternate metrics are an extension point for experimentation, and need only
+/// produce a `NodeDistance` which orders node ids consistently.
+pub trait DistanceMetric: Send + Sync {
+    /// Calculate the distance between `a` and `b`
+    fn distance(&self, a: &NodeId, b: &NodeId) -> NodeDistance;
+}
+
+/// The XOR distance metric, as used for DHT-style routing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XorDistance;
+
+impl DistanceMetric for XorDistance {
+    fn distance(&self, a: &NodeId, b: &NodeId) -> NodeDistance {
+        a.distance(b)
+    }
+}
@@ -32,6 +32,26 @@ pub enum PeerManagerError {
     BannedPeer,
     // An problem has been encountered with the database
     DatabaseError(KeyValStoreError),
+    /// Timed out waiting to acquire the peer storage lock
+    LockTimeout,
+    /// The given address and prefix length do not describe a valid IP subnet
+    InvalidSubnet,
+    /// The peer's node id is not derived from its public key
+    NodeIdMismatch,
+    /// An IO error occurred when exporting or importing peers
+    #[error(msg_embedded, no_from, non_std)]
+    IoError(String),
+    /// A peer record could not be serialized or deserialized
+    #[error(msg_embedded, no_from, non_std)]
+    SerializationError(String),
+    /// A mutation of the peer store panicked and the store must be repaired with `repair_integrity`
+    NeedsRepair,
+    /// The peer is this node and cannot be added to its own peer store
+    CannotAddSelf,
+    /// The peer's public key does not match the requested public key
+    PublicKeyMismatch,
+    /// The peer was rejected by the admission policy
+    AdmissionRejected,
 }
 
 impl PeerManagerError {
@@ -20,44 +20,179 @@
 //  WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+#[cfg(feature = "peer-store-lock-metrics")]
+use crate::peer_manager::metrics::{LockMetrics, LockWaitCounters};
 use crate::{
+    consts::{PEER_ADMISSION_QUARANTINE_PERIOD, PEER_MANAGER_CHANGE_LOG_BUFFER_SIZE},
     peer_manager::{
-        connection_stats::PeerConnectionStats,
+        admission::{AdmissionDecision, AdmissionPolicy},
+        ban_record::BanRecord,
+        binary_format::{self, BinaryReader},
+        change_log::{PeerStoreChange, PeerStoreChangeKind},
+        connection_stats::{ConnectionFailureReason, PeerConnectionStats},
+        distance_metric::DistanceMetric,
+        metrics::{PeerStoreCounters, PeerStoreMetrics},
         node_id::{NodeDistance, NodeId},
         peer::{Peer, PeerFlags},
+        peer_database::PeerDatabase,
+        peer_export::{PeerExport, ReconcileReport},
         peer_id::PeerId,
-        peer_storage::{PeerStorage, RegionStats},
+        peer_storage::{CompactionReport, FeatureCounts, PeerStorage, RegionStats},
+        selection_outcome::SelectionOutcome,
         PeerFeatures,
         PeerManagerError,
         PeerQuery,
     },
-    protocol::ProtocolId,
+    protocol::{self, ProtocolId},
     types::{CommsDatabase, CommsPublicKey},
+    utils::multiaddr::multiaddr_to_ip,
 };
+use cidr::{Inet, IpInet};
 use multiaddr::Multiaddr;
-use std::time::Duration;
-use tari_storage::IterationResult;
-use tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tari_crypto::tari_utilities::message_format::MessageFormat;
+use tari_storage::{HashmapDatabase, IterationResult};
+use tokio::{
+    sync::{broadcast, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time,
+};
 
 /// The PeerManager consist of a routing table of previously discovered peers.
 /// It also provides functionality to add, find and delete peers. A subset of peers can also be requested from the
 /// routing table based on the selected Broadcast strategy.
 pub struct PeerManager {
-    peer_storage: RwLock<PeerStorage<CommsDatabase>>,
+    peer_storage: RwLock<PeerStorage<PeerDatabase>>,
+    counters: PeerStoreCounters,
+    change_log: broadcast::Sender<Arc<PeerStoreChange>>,
+    needs_repair: AtomicBool,
+    local_public_key: std::sync::RwLock<Option<CommsPublicKey>>,
+    admission_policy: std::sync::RwLock<Option<AdmissionPolicy>>,
+    #[cfg(feature = "peer-store-lock-metrics")]
+    lock_wait_counters: LockWaitCounters,
 }
 
 impl PeerManager {
     /// Constructs a new empty PeerManager
     pub fn new(database: CommsDatabase) -> Result<PeerManager, PeerManagerError> {
-        Ok(Self {
-            peer_storage: RwLock::new(PeerStorage::new_indexed(database)?),
+        Ok(Self::from_storage(PeerStorage::new_indexed(PeerDatabase::Persistent(
+            database,
+        ))?))
+    }
+
+    /// Constructs a new empty PeerManager backed by an in-memory store. Peers are never persisted, so this is only
+    /// suitable for tests and ephemeral nodes.
+    pub fn new_in_memory() -> Result<PeerManager, PeerManagerError> {
+        Ok(Self::from_storage(PeerStorage::new_indexed(PeerDatabase::InMemory(
+            HashmapDatabase::new(),
+        ))?))
+    }
+
+    /// Constructs a new empty PeerManager which uses the given `DistanceMetric` to determine the closest peers and
+    /// network regions. `PeerManager::new` uses the XOR metric.
+    pub fn new_with_distance_metric(
+        database: CommsDatabase,
+        distance_metric: Arc<dyn DistanceMetric>,
+    ) -> Result<PeerManager, PeerManagerError>
+    {
+        Ok(Self::from_storage(PeerStorage::new_indexed_with_distance_metric(
+            PeerDatabase::Persistent(database),
+            distance_metric,
+        )?))
+    }
+
+    fn from_storage(peer_storage: PeerStorage<PeerDatabase>) -> Self {
+        let (change_log, _) = broadcast::channel(PEER_MANAGER_CHANGE_LOG_BUFFER_SIZE);
+        Self {
+            peer_storage: RwLock::new(peer_storage),
+            counters: Default::default(),
+            change_log,
+            needs_repair: AtomicBool::new(false),
+            local_public_key: std::sync::RwLock::new(None),
+            admission_policy: std::sync::RwLock::new(None),
+            #[cfg(feature = "peer-store-lock-metrics")]
+            lock_wait_counters: Default::default(),
+        }
+    }
+
+    /// Subscribe to the change log. Every mutation made to the peer store after subscribing is published as a
+    /// `PeerStoreChange`.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<Arc<PeerStoreChange>> {
+        self.change_log.subscribe()
+    }
+
+    /// Set the identity of this node. Once set, `add_peer` rejects a peer with this public key with
+    /// `PeerManagerError::CannotAddSelf`, so that this node never appears in its own peer selections.
+    pub fn set_local_identity(&self, public_key: CommsPublicKey) -> Result<(), PeerManagerError> {
+        *self.local_public_key.write()? = Some(public_key);
+        Ok(())
+    }
+
+    /// Set the policy that is consulted before a peer is added to the peer store by `add_peer`, `find_or_create` and
+    /// `reconcile`. Peers that are rejected by the policy are not added. Replaces any previously set policy.
+    pub fn set_admission_policy(&self, policy: AdmissionPolicy) -> Result<(), PeerManagerError> {
+        *self.admission_policy.write()? = Some(policy);
+        Ok(())
+    }
+
+    /// Returns the amount of time after which a peer that is marked as offline is considered by peer selection again
+    pub async fn offline_expiry(&self) -> Result<Duration, PeerManagerError> {
+        Ok(self.read_storage().await?.offline_expiry())
+    }
+
+    /// Set the amount of time after which a peer that is marked as offline is considered by peer selection and
+    /// connectability checks again. Defaults to [PEER_OFFLINE_EXPIRY](crate::consts::PEER_OFFLINE_EXPIRY).
+    pub async fn set_offline_expiry(&self, offline_expiry: Duration) -> Result<(), PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            storage.set_offline_expiry(offline_expiry);
+            Ok(())
         })
+        .await
+    }
+
+    /// Returns a snapshot of the peer store operation counters
+    pub fn operation_metrics(&self) -> PeerStoreMetrics {
+        self.counters.snapshot()
+    }
+
+    /// Returns histograms of the time spent waiting to acquire the peer storage read and write locks
+    #[cfg(feature = "peer-store-lock-metrics")]
+    pub fn lock_metrics(&self) -> LockMetrics {
+        self.lock_wait_counters.snapshot()
     }
 
     /// Adds a peer to the routing table of the PeerManager if the peer does not already exist. When a peer already
     /// exist, the stored version will be replaced with the newly provided peer.
-    pub async fn add_peer(&self, peer: Peer) -> Result<PeerId, PeerManagerError> {
-        self.peer_storage.write().await.add_peer(peer)
+    ///
+    /// Returns `PeerManagerError::NodeIdMismatch` if the peer's node id is not derived from its public key, and
+    /// `PeerManagerError::CannotAddSelf` if the peer is the local identity set with `set_local_identity`.
+    /// `PeerManagerError::AdmissionRejected` is returned if the peer is rejected by the admission policy.
+    pub async fn add_peer(&self, mut peer: Peer) -> Result<PeerId, PeerManagerError> {
+        Self::validate_node_id(&peer)?;
+        self.check_not_self(&peer)?;
+        self.admit(&mut peer)?;
+        self.write_storage_guarded(|storage| {
+            let node_id = peer.node_id.clone();
+            let peer_id = storage.add_peer(peer)?;
+            self.counters.inc_adds();
+            self.publish_change(|| {
+                let peer = storage.find_by_node_id(&node_id).ok()?;
+                Some(PeerStoreChange::new(
+                    node_id,
+                    PeerStoreChangeKind::Added(Box::new(peer)),
+                ))
+            });
+            Ok(peer_id)
+        })
+        .await
     }
 
     /// Updates fields for a peer. Any fields set to Some(xx) will be updated. All None
@@ -76,7 +211,7 @@ impl PeerManager {
         supported_protocols: Option<Vec<ProtocolId>>,
     ) -> Result<(), PeerManagerError>
     {
-        self.peer_storage.write().await.update_peer(
+        self.update_peer_returning(
             public_key,
             node_id,
             net_addresses,
@@ -87,79 +222,567 @@ impl PeerManager {
             connection_stats,
             supported_protocols,
         )
+        .await
+        .map(|_| ())
+    }
+
+    /// Updates fields for a peer in the same way as `update_peer` and returns the updated peer. The peer is fetched
+    /// under the same write lock as the update is applied.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update_peer_returning(
+        &self,
+        public_key: &CommsPublicKey,
+        node_id: Option<NodeId>,
+        net_addresses: Option<Vec<Multiaddr>>,
+        flags: Option<PeerFlags>,
+        #[allow(clippy::option_option)] banned_until: Option<Option<Duration>>,
+        #[allow(clippy::option_option)] is_offline: Option<bool>,
+        peer_features: Option<PeerFeatures>,
+        connection_stats: Option<PeerConnectionStats>,
+        supported_protocols: Option<Vec<ProtocolId>>,
+    ) -> Result<Peer, PeerManagerError>
+    {
+        self.write_storage_guarded(|storage| {
+            storage.update_peer(
+                public_key,
+                node_id,
+                net_addresses,
+                flags,
+                banned_until,
+                is_offline,
+                peer_features,
+                connection_stats,
+                supported_protocols,
+            )?;
+            self.counters.inc_updates();
+            let peer = storage.find_by_public_key(public_key)?;
+            self.publish_change(|| {
+                Some(PeerStoreChange::new(
+                    peer.node_id.clone(),
+                    PeerStoreChangeKind::Updated(Box::new(peer.clone())),
+                ))
+            });
+            Ok(peer)
+        })
+        .await
     }
 
     /// Set the last connection to this peer as a success
     pub async fn set_last_connect_success(&self, node_id: &NodeId) -> Result<(), PeerManagerError> {
-        let mut storage = self.peer_storage.write().await;
-        let mut peer = storage.find_by_node_id(node_id)?;
-        peer.connection_stats.set_connection_success();
-        storage.update_peer(
-            &peer.public_key,
-            None,
-            None,
-            None,
-            None,
-            Some(false),
-            None,
-            Some(peer.connection_stats),
-            None,
-        )
+        self.write_storage_guarded(|storage| {
+            let mut peer = storage.find_by_node_id(node_id)?;
+            peer.connection_stats.set_connection_success();
+            storage.update_peer(
+                &peer.public_key,
+                None,
+                None,
+                None,
+                None,
+                Some(false),
+                None,
+                Some(peer.connection_stats),
+                None,
+            )?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, node_id);
+            Ok(())
+        })
+        .await
     }
 
-    /// Set the last connection to this peer as a failure
+    /// Set the last connection to this peer as a failure for an unknown reason
     pub async fn set_last_connect_failed(&self, node_id: &NodeId) -> Result<(), PeerManagerError> {
-        let mut storage = self.peer_storage.write().await;
-        let mut peer = storage.find_by_node_id(node_id)?;
-        peer.connection_stats.set_connection_failed();
-        storage.update_peer(
-            &peer.public_key,
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(peer.connection_stats),
-            None,
-        )
+        self.set_last_connect_failed_with_reason(node_id, ConnectionFailureReason::Unknown)
+            .await
+    }
+
+    /// Set the last connection to this peer as a failure for the given reason
+    pub async fn set_last_connect_failed_with_reason(
+        &self,
+        node_id: &NodeId,
+        reason: ConnectionFailureReason,
+    ) -> Result<(), PeerManagerError>
+    {
+        self.write_storage_guarded(|storage| {
+            let mut peer = storage.find_by_node_id(node_id)?;
+            peer.connection_stats.set_connection_failed_with_reason(reason);
+            storage.update_peer(
+                &peer.public_key,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(peer.connection_stats),
+                None,
+            )?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Records that the peer closed an established connection to this node. A peer that does this repeatedly is
+    /// quarantined (see `Peer::is_quarantined`) and is not considered connectable for a period.
+    pub async fn record_peer_initiated_disconnect(&self, node_id: &NodeId) -> Result<(), PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            let mut peer = storage.find_by_node_id(node_id)?;
+            peer.connection_stats.record_peer_initiated_disconnect();
+            storage.update_peer(
+                &peer.public_key,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(peer.connection_stats),
+                None,
+            )?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Applies a batch of connection outcomes, given as `(node_id, was_successful)`, under a single write lock. As in
+    /// `set_last_connect_success`, a successful outcome also clears the peer's offline flag. `PeerNotFoundError` is
+    /// returned, and no outcomes are applied, if any of the peers do not exist.
+    pub async fn apply_connection_outcomes(&self, outcomes: &[(NodeId, bool)]) -> Result<(), PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            if !outcomes.iter().all(|(node_id, _)| storage.exists_node_id(node_id)) {
+                return Err(PeerManagerError::PeerNotFoundError);
+            }
+
+            for (node_id, was_successful) in outcomes {
+                // The peer is fetched for each outcome, as a peer may appear more than once
+                let mut peer = storage.find_by_node_id(node_id)?;
+                let is_offline = if *was_successful {
+                    peer.connection_stats.set_connection_success();
+                    Some(false)
+                } else {
+                    peer.connection_stats.set_connection_failed();
+                    None
+                };
+                storage.update_peer(
+                    &peer.public_key,
+                    None,
+                    None,
+                    None,
+                    None,
+                    is_offline,
+                    None,
+                    Some(peer.connection_stats),
+                    None,
+                )?;
+                self.publish_peer_updated(storage, node_id);
+            }
+            self.counters.add_updates(outcomes.len());
+            Ok(())
+        })
+        .await
     }
 
     /// The peer with the specified public_key will be removed from the PeerManager
     pub async fn delete_peer(&self, node_id: &NodeId) -> Result<(), PeerManagerError> {
-        self.peer_storage.write().await.delete_peer(node_id)
+        self.write_storage_guarded(|storage| storage.delete_peer(node_id))
+            .await?;
+        self.counters.inc_deletes();
+        self.publish_change(|| Some(PeerStoreChange::new(node_id.clone(), PeerStoreChangeKind::Deleted)));
+        Ok(())
+    }
+
+    /// The peer with the specified public_key will be removed from the PeerManager. `PeerNotFoundError` is returned if
+    /// the peer does not exist.
+    pub async fn delete_by_public_key(&self, public_key: &CommsPublicKey) -> Result<(), PeerManagerError> {
+        let node_id = self
+            .write_storage_guarded(|storage| storage.delete_by_public_key(public_key))
+            .await?;
+        self.counters.inc_deletes();
+        self.publish_change(|| Some(PeerStoreChange::new(node_id, PeerStoreChangeKind::Deleted)));
+        Ok(())
+    }
+
+    /// Deletes all peers for which the `predicate` returns true and returns the number of peers deleted. Seed peers are
+    /// never deleted, even if they match the predicate.
+    pub async fn delete_where<F>(&self, mut predicate: F) -> Result<usize, PeerManagerError>
+    where F: FnMut(&Peer) -> bool {
+        self.write_storage_guarded(|storage| {
+            let mut node_ids = Vec::new();
+            storage.for_each(|peer| {
+                if !peer.is_seed() && (predicate)(&peer) {
+                    node_ids.push(peer.node_id);
+                }
+                IterationResult::Continue
+            })?;
+
+            for node_id in &node_ids {
+                storage.delete_peer(node_id)?;
+                self.counters.inc_deletes();
+                self.publish_change(|| Some(PeerStoreChange::new(node_id.clone(), PeerStoreChangeKind::Deleted)));
+            }
+
+            Ok(node_ids.len())
+        })
+        .await
+    }
+
+    /// Writes every peer to `writer` as newline-delimited JSON records and returns the number of peers written. Peers
+    /// are written as they are read from the peer storage, so the store is never buffered in memory.
+    pub async fn export_to_writer<W: Write>(&self, mut writer: W) -> Result<usize, PeerManagerError> {
+        let storage = self.read_storage().await?;
+        let mut num_exported = 0;
+        let mut write_result = Ok(());
+        storage.for_each(|peer| {
+            write_result = peer
+                .to_json()
+                .map_err(|err| PeerManagerError::SerializationError(err.to_string()))
+                .and_then(|json| {
+                    writeln!(writer, "{}", json).map_err(|err| PeerManagerError::IoError(err.to_string()))
+                });
+            if write_result.is_err() {
+                return IterationResult::Break;
+            }
+            num_exported += 1;
+            IterationResult::Continue
+        })?;
+        write_result?;
+        writer
+            .flush()
+            .map_err(|err| PeerManagerError::IoError(err.to_string()))?;
+        Ok(num_exported)
+    }
+
+    /// Reads newline-delimited JSON peer records, as written by `export_to_writer`, from `reader` and adds each peer.
+    /// Existing peers are replaced. Returns the number of peers imported.
+    pub async fn import_from_reader<R: Read>(&self, reader: R) -> Result<usize, PeerManagerError> {
+        let mut num_imported = 0;
+        for line in BufReader::new(reader).lines() {
+            let line = line.map_err(|err| PeerManagerError::IoError(err.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let peer = Peer::from_json(&line).map_err(|err| PeerManagerError::SerializationError(err.to_string()))?;
+            self.add_peer(peer).await?;
+            num_imported += 1;
+        }
+        Ok(num_imported)
+    }
+
+    /// Returns the shareable `PeerExport` of every peer for which the `predicate` returns true, e.g. to produce a seed
+    /// list of reliable base nodes
+    pub async fn export_where<F>(&self, predicate: F) -> Result<Vec<PeerExport>, PeerManagerError>
+    where F: Fn(&Peer) -> bool {
+        let mut exports = Vec::new();
+        self.read_storage().await?.for_each(|peer| {
+            if predicate(&peer) {
+                exports.push(PeerExport::from(&peer));
+            }
+            IterationResult::Continue
+        })?;
+        Ok(exports)
+    }
+
+    /// Makes the peer store match an authoritative `snapshot`. Peers in the snapshot that are not known are added and
+    /// known peers whose shareable fields differ are updated. Local state (bans, offline and quarantine state, flags,
+    /// connection stats and aliases) is kept for updated peers. If `remove_missing` is true, peers that are not in the
+    /// snapshot are deleted, except for seed peers and banned peers so that a ban cannot be lifted by a snapshot.
+    /// Snapshot entries with a node id that does not match the public key, for the local node or for new peers that are
+    /// rejected by the admission policy are ignored.
+    pub async fn reconcile(
+        &self,
+        snapshot: Vec<PeerExport>,
+        remove_missing: bool,
+    ) -> Result<ReconcileReport, PeerManagerError>
+    {
+        let local_public_key = self.local_public_key.read()?.clone();
+        self.write_storage_guarded(|storage| {
+            let mut report = ReconcileReport::default();
+
+            for export in &snapshot {
+                if local_public_key.as_ref() == Some(&export.public_key) ||
+                    NodeId::from_key(&export.public_key).ok().as_ref() != Some(&export.node_id)
+                {
+                    continue;
+                }
+                match storage.find_by_public_key(&export.public_key) {
+                    Ok(peer) => {
+                        if !export.differs_from(&peer) {
+                            continue;
+                        }
+                        storage.update_peer(
+                            &export.public_key,
+                            Some(export.node_id.clone()),
+                            Some(export.addresses.clone()),
+                            None,
+                            None,
+                            None,
+                            Some(export.features),
+                            None,
+                            Some(export.supported_protocols.clone()),
+                        )?;
+                        self.counters.inc_updates();
+                        self.publish_peer_updated(storage, &export.node_id);
+                        report.updated += 1;
+                    },
+                    Err(PeerManagerError::PeerNotFoundError) => {
+                        let mut peer = Peer::new(
+                            export.public_key.clone(),
+                            export.node_id.clone(),
+                            export.addresses.clone().into(),
+                            PeerFlags::default(),
+                            export.features,
+                            &export.supported_protocols,
+                        );
+                        match self.admit(&mut peer) {
+                            Ok(_) => {},
+                            Err(PeerManagerError::AdmissionRejected) => continue,
+                            Err(err) => return Err(err),
+                        }
+                        storage.add_peer(peer)?;
+                        self.counters.inc_adds();
+                        self.publish_change(|| {
+                            let peer = storage.find_by_node_id(&export.node_id).ok()?;
+                            Some(PeerStoreChange::new(
+                                export.node_id.clone(),
+                                PeerStoreChangeKind::Added(Box::new(peer)),
+                            ))
+                        });
+                        report.added += 1;
+                    },
+                    Err(err) => return Err(err),
+                }
+            }
+
+            if remove_missing {
+                let mut node_ids = Vec::new();
+                storage.for_each(|peer| {
+                    if !peer.is_seed() &&
+                        !peer.is_banned() &&
+                        snapshot.iter().all(|export| export.public_key != peer.public_key)
+                    {
+                        node_ids.push(peer.node_id);
+                    }
+                    IterationResult::Continue
+                })?;
+                for node_id in node_ids {
+                    storage.delete_peer(&node_id)?;
+                    self.counters.inc_deletes();
+                    self.publish_change(|| Some(PeerStoreChange::new(node_id, PeerStoreChangeKind::Deleted)));
+                    report.removed += 1;
+                }
+            }
+
+            Ok(report)
+        })
+        .await
+    }
+
+    /// Encodes every peer in the peer record binary format (see `Peer::to_bytes`). Each record is prefixed with its
+    /// length. This is much more compact than `export_to_writer`, but does not include any local peer state.
+    pub async fn export_peers_binary(&self) -> Result<Vec<u8>, PeerManagerError> {
+        let mut buf = Vec::new();
+        self.read_storage().await?.for_each(|peer| {
+            binary_format::write_bytes(&mut buf, &peer.to_bytes());
+            IterationResult::Continue
+        })?;
+        Ok(buf)
+    }
+
+    /// Decodes and adds the peers in `bytes`, as written by `export_peers_binary`. Existing peers are replaced.
+    /// Returns the number of peers imported.
+    pub async fn import_peers_binary(&self, bytes: &[u8]) -> Result<usize, PeerManagerError> {
+        let mut reader = BinaryReader::new(bytes);
+        let mut num_imported = 0;
+        while !reader.is_empty() {
+            let peer = reader
+                .read_bytes()
+                .and_then(Peer::from_bytes)
+                .map_err(|err| PeerManagerError::SerializationError(err.to_string()))?;
+            self.add_peer(peer).await?;
+            num_imported += 1;
+        }
+        Ok(num_imported)
+    }
+
+    /// Rebuilds the peer storage indexes from the stored peers, discarding stale entries and restoring missing ones.
+    /// This is a maintenance operation that may be run periodically on long-lived nodes.
+    pub async fn compact(&self) -> Result<CompactionReport, PeerManagerError> {
+        self.write_storage_guarded(|storage| storage.compact()).await
     }
 
     /// Performs the given [PeerQuery].
     ///
     /// [PeerQuery]: crate::peer_manager::peer_query::PeerQuery
     pub async fn perform_query(&self, peer_query: PeerQuery<'_>) -> Result<Vec<Peer>, PeerManagerError> {
-        self.peer_storage.read().await.perform_query(peer_query)
+        self.read_storage().await?.perform_query(peer_query)
+    }
+
+    /// Performs each of the given [PeerQuery]s under a single read lock, so that the results are a consistent
+    /// snapshot of the peer store. The results are returned in the same order as the queries.
+    ///
+    /// [PeerQuery]: crate::peer_manager::peer_query::PeerQuery
+    pub async fn perform_queries(&self, queries: Vec<PeerQuery<'_>>) -> Result<Vec<Vec<Peer>>, PeerManagerError> {
+        let storage = self.read_storage().await?;
+        queries.into_iter().map(|query| storage.perform_query(query)).collect()
+    }
+
+    /// Returns all non-banned peers that advertise support for a protocol in the given protocol `family` (e.g.
+    /// `/tari/messaging`) with a major version of at least `min_version`.
+    pub async fn peers_supporting_protocol_version(
+        &self,
+        family: &[u8],
+        min_version: u32,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let query = PeerQuery::new().select_where(|peer| {
+            !peer.is_banned() &&
+                peer.supported_protocols()
+                    .iter()
+                    .filter_map(protocol::split_protocol_id)
+                    .any(|(f, version)| f == family && version >= min_version)
+        });
+        self.perform_query(query).await
+    }
+
+    /// Returns all non-banned peers, other than the referenced peer, with the given features (or any features if None)
+    /// that share at least one supported protocol with the peer with the given node id
+    pub async fn peers_compatible_with(
+        &self,
+        node_id: &NodeId,
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let storage = self.read_storage().await?;
+        let reference = storage.find_by_node_id(node_id)?;
+        let query = PeerQuery::new().select_where(|peer| {
+            !peer.is_banned() &&
+                peer.node_id != reference.node_id &&
+                features.map(|f| peer.features == f).unwrap_or(true) &&
+                peer.supported_protocols()
+                    .iter()
+                    .any(|protocol| reference.supported_protocols().contains(protocol))
+        });
+        storage.perform_query(query)
     }
 
     /// Find the peer with the provided NodeID
     pub async fn find_by_node_id(&self, node_id: &NodeId) -> Result<Peer, PeerManagerError> {
-        self.peer_storage.read().await.find_by_node_id(node_id)
+        let result = self.read_storage().await?.find_by_node_id(node_id);
+        self.record_lookup(&result);
+        result
     }
 
     /// Find the peer with the provided PublicKey
     pub async fn find_by_public_key(&self, public_key: &CommsPublicKey) -> Result<Peer, PeerManagerError> {
-        self.peer_storage.read().await.find_by_public_key(public_key)
+        let result = self.read_storage().await?.find_by_public_key(public_key);
+        self.record_lookup(&result);
+        result
+    }
+
+    /// Find the peer with the provided PublicKey, or add the peer returned by `make` if it does not exist. The lookup
+    /// and insert are performed under the same write lock, so concurrent callers never add the peer more than once.
+    /// `make` is only called if the peer does not exist and must return a peer with the given public key, otherwise
+    /// `PeerManagerError::PublicKeyMismatch` is returned.
+    pub async fn find_or_create<F>(&self, public_key: &CommsPublicKey, make: F) -> Result<Peer, PeerManagerError>
+    where F: FnOnce() -> Peer {
+        self.write_storage_guarded(|storage| {
+            let result = storage.find_by_public_key(public_key);
+            self.record_lookup(&result);
+            match result {
+                Ok(peer) => return Ok(peer),
+                Err(PeerManagerError::PeerNotFoundError) => {},
+                Err(err) => return Err(err),
+            }
+
+            let mut peer = make();
+            if peer.public_key != *public_key {
+                return Err(PeerManagerError::PublicKeyMismatch);
+            }
+            Self::validate_node_id(&peer)?;
+            self.check_not_self(&peer)?;
+            self.admit(&mut peer)?;
+            let node_id = peer.node_id.clone();
+            storage.add_peer(peer)?;
+            self.counters.inc_adds();
+            let peer = storage.find_by_node_id(&node_id)?;
+            self.publish_change(|| {
+                Some(PeerStoreChange::new(
+                    node_id,
+                    PeerStoreChangeKind::Added(Box::new(peer.clone())),
+                ))
+            });
+            Ok(peer)
+        })
+        .await
+    }
+
+    /// Returns the current revision of the peer store. This can be used as a checkpoint for `peers_changed_since`.
+    pub async fn revision(&self) -> Result<u64, PeerManagerError> {
+        Ok(self.read_storage().await?.revision())
+    }
+
+    /// Returns all peers that have been added or updated since the given revision, ordered by their revision. Deleted
+    /// peers are not included.
+    pub async fn peers_changed_since(&self, revision: u64) -> Result<Vec<Peer>, PeerManagerError> {
+        self.read_storage().await?.peers_changed_since(revision)
+    }
+
+    /// Find the first peer with the given alias. Aliases are not required to be unique.
+    pub async fn find_by_alias(&self, alias: &str) -> Result<Peer, PeerManagerError> {
+        let result = self.read_storage().await?.find_by_alias(alias);
+        self.record_lookup(&result);
+        result
+    }
+
+    /// Sets or clears the human-friendly alias of a peer. The alias is only used for display purposes and does not
+    /// affect peer selection.
+    pub async fn set_alias(&self, node_id: &NodeId, alias: Option<String>) -> Result<(), PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            let mut peer = storage.find_by_node_id(node_id)?;
+            peer.alias = alias;
+            storage.add_peer(peer)?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, node_id);
+            Ok(())
+        })
+        .await
     }
 
     /// Check if a peer exist using the specified public_key
     pub async fn exists(&self, public_key: &CommsPublicKey) -> bool {
-        self.peer_storage.read().await.exists(public_key)
+        let exists = self.acquire_read().await.exists(public_key);
+        self.counters.record_lookup(exists);
+        exists
     }
 
     /// Check if a peer exist using the specified node_id
     pub async fn exists_node_id(&self, node_id: &NodeId) -> bool {
-        self.peer_storage.read().await.exists_node_id(node_id)
+        let exists = self.acquire_read().await.exists_node_id(node_id);
+        self.counters.record_lookup(exists);
+        exists
+    }
+
+    /// Check if peers exist for each of the given node ids. The returned vec contains a bool for each node id, in the
+    /// same order as `node_ids`.
+    pub async fn exists_node_ids(&self, node_ids: &[NodeId]) -> Result<Vec<bool>, PeerManagerError> {
+        let storage = self.read_storage().await?;
+        Ok(node_ids
+            .iter()
+            .map(|node_id| {
+                let exists = storage.exists_node_id(node_id);
+                self.counters.record_lookup(exists);
+                exists
+            })
+            .collect())
     }
 
     /// Returns all peers
     pub async fn all(&self) -> Result<Vec<Peer>, PeerManagerError> {
-        self.peer_storage.read().await.all()
+        self.read_storage().await?.all()
     }
 
     /// Adds or updates a peer and sets the last connection as successful.
@@ -172,42 +795,69 @@ impl PeerManager {
         peer_features: PeerFeatures,
     ) -> Result<Peer, PeerManagerError>
     {
-        match self.find_by_public_key(&pubkey).await {
-            Ok(mut peer) => {
-                peer.connection_stats.set_connection_success();
-                peer.update(
-                    Some(node_id),
-                    Some(net_addresses),
-                    None,
-                    None,
-                    Some(false),
-                    Some(peer_features),
-                    None,
-                    None,
-                );
-                self.add_peer(peer.clone()).await?;
-                Ok(peer)
-            },
-            Err(PeerManagerError::PeerNotFoundError) => {
-                self.add_peer(Peer::new(
-                    pubkey.clone(),
-                    node_id,
-                    net_addresses.into(),
-                    PeerFlags::default(),
-                    peer_features,
-                    &[],
-                ))
-                .await?;
+        self.write_storage_guarded(|storage| {
+            let result = storage.find_by_public_key(pubkey);
+            self.record_lookup(&result);
+            let (mut peer, is_new) = match result {
+                Ok(mut peer) => {
+                    peer.connection_stats.set_connection_success();
+                    peer.update(
+                        Some(node_id),
+                        Some(net_addresses),
+                        None,
+                        None,
+                        Some(false),
+                        Some(peer_features),
+                        None,
+                        None,
+                    );
+                    (peer, false)
+                },
+                Err(PeerManagerError::PeerNotFoundError) => {
+                    let peer = Peer::new(
+                        pubkey.clone(),
+                        node_id,
+                        net_addresses.into(),
+                        PeerFlags::default(),
+                        peer_features,
+                        &[],
+                    );
+                    (peer, true)
+                },
+                Err(err) => return Err(err),
+            };
 
-                self.find_by_public_key(&pubkey).await
-            },
-            Err(err) => Err(err),
-        }
+            Self::validate_node_id(&peer)?;
+            self.check_not_self(&peer)?;
+            self.admit(&mut peer)?;
+            let node_id = peer.node_id.clone();
+            storage.add_peer(peer)?;
+            let peer = storage.find_by_node_id(&node_id)?;
+            if is_new {
+                self.counters.inc_adds();
+                self.publish_change(|| {
+                    Some(PeerStoreChange::new(
+                        node_id,
+                        PeerStoreChangeKind::Added(Box::new(peer.clone())),
+                    ))
+                });
+            } else {
+                self.counters.inc_updates();
+                self.publish_change(|| {
+                    Some(PeerStoreChange::new(
+                        node_id,
+                        PeerStoreChangeKind::Updated(Box::new(peer.clone())),
+                    ))
+                });
+            }
+            Ok(peer)
+        })
+        .await
     }
 
     /// Get a peer matching the given node ID
     pub async fn direct_identity_node_id(&self, node_id: &NodeId) -> Result<Option<Peer>, PeerManagerError> {
-        match self.peer_storage.read().await.direct_identity_node_id(&node_id) {
+        match self.read_storage().await?.direct_identity_node_id(&node_id) {
             Ok(peer) => Ok(Some(peer)),
             Err(PeerManagerError::PeerNotFoundError) | Err(PeerManagerError::BannedPeer) => Ok(None),
             Err(err) => Err(err),
@@ -220,7 +870,7 @@ impl PeerManager {
         public_key: &CommsPublicKey,
     ) -> Result<Option<Peer>, PeerManagerError>
     {
-        match self.peer_storage.read().await.direct_identity_public_key(&public_key) {
+        match self.read_storage().await?.direct_identity_public_key(&public_key) {
             Ok(peer) => Ok(Some(peer)),
             Err(PeerManagerError::PeerNotFoundError) | Err(PeerManagerError::BannedPeer) => Ok(None),
             Err(err) => Err(err),
@@ -229,12 +879,30 @@ impl PeerManager {
 
     /// Fetch all peers (except banned ones)
     pub async fn flood_peers(&self) -> Result<Vec<Peer>, PeerManagerError> {
-        self.peer_storage.read().await.flood_peers()
+        self.read_storage().await?.flood_peers()
     }
 
     pub async fn for_each<F>(&self, f: F) -> Result<(), PeerManagerError>
     where F: FnMut(Peer) -> IterationResult {
-        self.peer_storage.read().await.for_each(f)
+        self.read_storage().await?.for_each(f)
+    }
+
+    /// The fallible counterpart of `for_each`. Iteration stops at the first error returned by `f`, and that error is
+    /// returned.
+    pub async fn try_for_each<F, E>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(Peer) -> Result<IterationResult, E>,
+        E: From<PeerManagerError>,
+    {
+        let mut result = Ok(());
+        self.read_storage().await?.for_each(|peer| match f(peer) {
+            Ok(iteration_result) => iteration_result,
+            Err(err) => {
+                result = Err(err);
+                IterationResult::Break
+            },
+        })?;
+        result
     }
 
     /// Fetch n nearest neighbours. If features are supplied, the function will return the closest peers matching that
@@ -247,110 +915,761 @@ impl PeerManager {
         features: Option<PeerFeatures>,
     ) -> Result<Vec<Peer>, PeerManagerError>
     {
-        self.peer_storage
-            .read()
-            .await
+        self.read_storage()
+            .await?
             .closest_peers(node_id, n, excluded_peers, features)
     }
 
-    /// Fetch n random peers
-    pub async fn random_peers(&self, n: usize, excluded: Vec<NodeId>) -> Result<Vec<Peer>, PeerManagerError> {
-        // Send to a random set of peers of size n that are Communication Nodes
-        self.peer_storage.read().await.random_peers(n, excluded)
+    /// Fetch n nearest neighbours, as `closest_peers` does, and describe the outcome of the selection. If fewer than
+    /// n peers are selected, the outcome distinguishes between an empty peer store, a store without any eligible
+    /// peers and a store with fewer eligible peers than requested, so that the caller can decide whether to discover
+    /// more peers or to proceed with the peers that were selected.
+    pub async fn closest_peers_outcome(
+        &self,
+        node_id: &NodeId,
+        n: usize,
+        excluded_peers: &[CommsPublicKey],
+        features: Option<PeerFeatures>,
+    ) -> Result<SelectionOutcome, PeerManagerError>
+    {
+        let storage = self.read_storage().await?;
+        let peers = storage.closest_peers(node_id, n, excluded_peers, features)?;
+        let outcome = if peers.len() >= n {
+            SelectionOutcome::Complete(peers)
+        } else if !peers.is_empty() {
+            SelectionOutcome::Insufficient(peers)
+        } else if storage.is_empty() {
+            SelectionOutcome::NoPeers
+        } else {
+            SelectionOutcome::NoEligiblePeers
+        };
+        Ok(outcome)
     }
 
-    /// Check if a specific node_id is in the network region of the N nearest neighbours of the region specified by
-    /// region_node_id
-    pub async fn in_network_region(
+    /// Fetch up to n of the nearest neighbours that can be dialed right now (see `Peer::is_connectable`). The
+    /// connectability check is applied during the distance scan, so unlike filtering the result of `closest_peers`,
+    /// the result is only under-filled if there are fewer than n connectable peers.
+    pub async fn closest_eligible_peers(
         &self,
-        node_id: &NodeId,
-        region_node_id: &NodeId,
+        target: &NodeId,
         n: usize,
-    ) -> Result<bool, PeerManagerError>
+        excluded_peers: &[CommsPublicKey],
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
     {
-        self.peer_storage
-            .read()
-            .await
-            .in_network_region(node_id, region_node_id, n)
+        self.read_storage()
+            .await?
+            .closest_eligible_peers(target, n, excluded_peers, features)
     }
 
-    pub async fn calc_region_threshold(
+    /// Fetch up to n of the nearest peers to `target` for which `predicate` returns true. The predicate is applied
+    /// during the distance scan, so the result is only under-filled if there are fewer than n matching peers. Unlike
+    /// `closest_peers`, banned and offline peers are only excluded if the predicate excludes them.
+    pub async fn closest_peers_where<F>(
         &self,
-        region_node_id: &NodeId,
+        target: &NodeId,
         n: usize,
-        features: PeerFeatures,
-    ) -> Result<NodeDistance, PeerManagerError>
+        predicate: F,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    where
+        F: Fn(&Peer) -> bool,
     {
-        self.peer_storage
-            .read()
-            .await
-            .calc_region_threshold(region_node_id, n, features)
+        self.read_storage().await?.closest_peers_where(target, n, predicate)
     }
 
-    /// Unbans the peer if it is banned. This function is idempotent.
-    pub async fn unban(&self, public_key: &CommsPublicKey) -> Result<NodeId, PeerManagerError> {
-        self.peer_storage.write().await.unban(public_key)
+    /// Returns the `replicas` successors of `key` on the distance ring, i.e. the peers responsible for `key` in a
+    /// DHT-style assignment. Under the XOR metric the successors of a key are exactly its closest peers, so this is
+    /// equivalent to `closest_peers` without excluded peers. Banned and offline peers are not included.
+    pub async fn ring_successors(
+        &self,
+        key: &NodeId,
+        replicas: usize,
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        self.read_storage().await?.closest_peers(key, replicas, &[], features)
     }
 
-    /// Ban the peer for a length of time specified by the duration
-    pub async fn ban_for(&self, public_key: &CommsPublicKey, duration: Duration) -> Result<NodeId, PeerManagerError> {
-        self.peer_storage.write().await.ban_for(public_key, duration)
+    /// Fetch the n nearest neighbours of `target` for each of the given feature groups. This scans the peer store
+    /// once, rather than once per group as calling `closest_peers` for each group would.
+    pub async fn closest_peers_by_feature_groups(
+        &self,
+        target: &NodeId,
+        n: usize,
+        groups: &[PeerFeatures],
+    ) -> Result<HashMap<PeerFeatures, Vec<Peer>>, PeerManagerError>
+    {
+        self.read_storage()
+            .await?
+            .closest_peers_by_feature_groups(target, n, groups)
     }
 
-    /// Changes the offline flag bit of the peer
-    pub async fn set_offline(&self, public_key: &CommsPublicKey, is_offline: bool) -> Result<NodeId, PeerManagerError> {
-        self.peer_storage.write().await.set_offline(public_key, is_offline)
+    /// Fetch a page of peers that are not banned or offline, ordered by their distance to `key`. Each peer is returned
+    /// along with its distance to `key`. The first `offset` peers are skipped and at most `limit` peers are returned.
+    pub async fn peers_ranked_by_distance(
+        &self,
+        key: &NodeId,
+        offset: usize,
+        limit: usize,
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<(Peer, NodeDistance)>, PeerManagerError>
+    {
+        self.read_storage()
+            .await?
+            .peers_ranked_by_distance(key, offset, limit, features)
     }
 
-    /// Adds a new net address to the peer if it doesn't yet exist
-    pub async fn add_net_address(&self, node_id: &NodeId, net_address: &Multiaddr) -> Result<(), PeerManagerError> {
-        self.peer_storage.write().await.add_net_address(node_id, net_address)
+    /// Fetch the nearest eligible peer to `target` along with the next nearest as a backup, should the primary peer be
+    /// unreachable. Peers are selected in the same way as `closest_peers`. None is returned if there are no eligible
+    /// peers.
+    pub async fn nearest_with_backup(
+        &self,
+        target: &NodeId,
+        features: Option<PeerFeatures>,
+    ) -> Result<Option<(Peer, Option<Peer>)>, PeerManagerError>
+    {
+        let mut peers = self
+            .read_storage()
+            .await?
+            .closest_peers(target, 2, &[], features)?
+            .into_iter();
+        Ok(peers.next().map(|primary| (primary, peers.next())))
     }
 
-    pub async fn update_each<F>(&self, mut f: F) -> Result<usize, PeerManagerError>
-    where F: FnMut(Peer) -> Option<Peer> {
-        let mut lock = self.peer_storage.write().await;
-        let mut peers_to_update = Vec::new();
-        lock.for_each(|peer| {
-            if let Some(peer) = (f)(peer) {
-                peers_to_update.push(peer);
-            }
-            IterationResult::Continue
-        })?;
+    /// Fetch n nearest neighbours in the same way as `closest_peers`, however if the peer storage lock cannot be
+    /// acquired within the given `timeout` a `PeerManagerError::LockTimeout` error is returned. This allows
+    /// latency-sensitive callers to fall back to a previous result rather than waiting on a contended lock.
+    pub async fn try_closest_peers(
+        &self,
+        node_id: &NodeId,
+        n: usize,
+        excluded_peers: &[CommsPublicKey],
+        features: Option<PeerFeatures>,
+        timeout: Duration,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        self.check_integrity()?;
+        let storage = time::timeout(timeout, self.acquire_read())
+            .await
+            .map_err(|_| PeerManagerError::LockTimeout)?;
+        storage.closest_peers(node_id, n, excluded_peers, features)
+    }
 
-        let updated_count = peers_to_update.len();
-        for p in peers_to_update {
-            lock.add_peer(p)?;
-        }
+    /// Select a route of up to `hops` peers toward `target` for multi-hop routing. Each peer in the route is strictly
+    /// closer to the target than the previous one, ending with the closest connectable peer to the target. If the
+    /// local identity is set, only peers closer to the target than this node are used. A shorter route is returned if
+    /// there are not enough peers that make progress toward the target.
+    pub async fn build_route(
+        &self,
+        target: &NodeId,
+        hops: usize,
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let origin = self
+            .local_public_key
+            .read()?
+            .as_ref()
+            .and_then(|public_key| NodeId::from_key(public_key).ok());
+        self.read_storage()
+            .await?
+            .build_route(target, hops, features, origin.as_ref())
+    }
 
-        Ok(updated_count)
+    /// Fetch n random peers
+    pub async fn random_peers(&self, n: usize, excluded: Vec<NodeId>) -> Result<Vec<Peer>, PeerManagerError> {
+        // Send to a random set of peers of size n that are Communication Nodes
+        self.read_storage().await?.random_peers(n, excluded)
     }
 
-    /// Return some basic stats about the region around region_node_id
-    pub async fn get_region_stats<'a>(
+    /// Fetch up to n random peers, in the same way as `random_peers`, where no two peers share an IP subnet of the
+    /// given prefix length. Peers that share a subnet are only returned if there are not enough peers in distinct
+    /// subnets.
+    pub async fn random_peers_distinct_subnets(
         &self,
-        region_node_id: &'a NodeId,
         n: usize,
-        features: PeerFeatures,
-    ) -> Result<RegionStats<'a>, PeerManagerError>
+        prefix_len: u8,
+        excluded: Vec<NodeId>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
     {
-        self.peer_storage
-            .read()
-            .await
-            .get_region_stats(region_node_id, n, features)
+        self.read_storage()
+            .await?
+            .random_peers_distinct_subnets(n, prefix_len, excluded)
     }
 
-    pub async fn get_peer_features(&self, node_id: &NodeId) -> Result<PeerFeatures, PeerManagerError> {
-        // TODO: #sqliterefactor fetch the features with a sql query
-        let peer = self.find_by_node_id(node_id).await?;
-        Ok(peer.features)
+    /// Returns true if the peer has at least one address that can be dialed. `PeerNotFoundError` is returned if the
+    /// peer does not exist.
+    pub async fn has_addresses(&self, node_id: &NodeId) -> Result<bool, PeerManagerError> {
+        self.read_storage().await?.has_addresses(node_id)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::{
+    /// Returns the instant at which the peer is next eligible to be dialed, or None if its ban, offline flag, offline
+    /// cooldown period or quarantine do not currently prevent it from being dialed. This can be used to schedule a dial
+    /// for exactly when the peer becomes eligible, rather than polling.
+    pub async fn connectable_at(&self, node_id: &NodeId) -> Result<Option<Instant>, PeerManagerError> {
+        let storage = self.read_storage().await?;
+        let result = storage.find_by_node_id(node_id);
+        self.record_lookup(&result);
+        Ok(result?
+            .connectable_in(storage.offline_expiry())
+            .map(|remaining| Instant::now() + remaining))
+    }
+
+    /// Returns true if there is at least one peer with the given features that can be dialed. This is a cheap
+    /// precondition check that does not require the peers to be sorted.
+    pub async fn any_connectable(&self, features: Option<PeerFeatures>) -> Result<bool, PeerManagerError> {
+        self.read_storage().await?.any_connectable(features)
+    }
+
+    /// Returns true if there are at least `min` peers with the given features that can be dialed (see
+    /// `Peer::is_connectable`). The scan stops as soon as `min` connectable peers are found, so this is suitable as a
+    /// precondition check before starting a service.
+    pub async fn has_min_connectable(
+        &self,
+        features: Option<PeerFeatures>,
+        min: usize,
+    ) -> Result<bool, PeerManagerError>
+    {
+        self.read_storage().await?.has_min_connectable(features, min)
+    }
+
+    /// Select n peers with the given features that are ordered by the hash of `seed` and their node id. Nodes with the
+    /// same peers select the same subset for a given seed (e.g. an epoch seed), which can be used to coordinate which
+    /// peers are contacted across the network. Banned and offline peers are excluded.
+    pub async fn deterministic_peers(
+        &self,
+        seed: &[u8],
+        n: usize,
+        features: PeerFeatures,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        self.read_storage().await?.deterministic_peers(seed, n, features)
+    }
+
+    /// Returns the total, online, offline and banned peer counts for each distinct set of peer features in the store
+    pub async fn feature_breakdown(&self) -> Result<HashMap<PeerFeatures, FeatureCounts>, PeerManagerError> {
+        self.read_storage().await?.feature_breakdown()
+    }
+
+    /// Check if a specific node_id is in the network region of the N nearest neighbours of the region specified by
+    /// region_node_id
+    pub async fn in_network_region(
+        &self,
+        node_id: &NodeId,
+        region_node_id: &NodeId,
+        n: usize,
+    ) -> Result<bool, PeerManagerError>
+    {
+        self.read_storage().await?.in_network_region(node_id, region_node_id, n)
+    }
+
+    pub async fn calc_region_threshold(
+        &self,
+        region_node_id: &NodeId,
+        n: usize,
+        features: PeerFeatures,
+    ) -> Result<NodeDistance, PeerManagerError>
+    {
+        self.read_storage()
+            .await?
+            .calc_region_threshold(region_node_id, n, features)
+    }
+
+    /// Returns the XOR distance between two node ids. Neither node id needs to be in the peer store.
+    pub fn distance_between(&self, a: &NodeId, b: &NodeId) -> NodeDistance {
+        a.distance(b)
+    }
+
+    /// Returns the XOR distance between two peers, or `PeerManagerError::PeerNotFoundError` if either peer is not in
+    /// the peer store.
+    pub async fn peer_distance(&self, a: &NodeId, b: &NodeId) -> Result<NodeDistance, PeerManagerError> {
+        let storage = self.read_storage().await?;
+        for node_id in &[a, b] {
+            let exists = storage.exists_node_id(node_id);
+            self.counters.record_lookup(exists);
+            if !exists {
+                return Err(PeerManagerError::PeerNotFoundError);
+            }
+        }
+        Ok(self.distance_between(a, b))
+    }
+
+    /// Unbans the peer if it is banned. This function is idempotent.
+    pub async fn unban(&self, public_key: &CommsPublicKey) -> Result<NodeId, PeerManagerError> {
+        let node_id = self.write_storage_guarded(|storage| storage.unban(public_key)).await?;
+        self.counters.inc_updates();
+        self.publish_change(|| Some(PeerStoreChange::new(node_id.clone(), PeerStoreChangeKind::Unbanned)));
+        Ok(node_id)
+    }
+
+    /// Unbans all banned peers for which the `predicate` returns true and returns the number of peers unbanned
+    pub async fn unban_where<F>(&self, predicate: F) -> Result<usize, PeerManagerError>
+    where F: Fn(&Peer) -> bool {
+        self.write_storage_guarded(|storage| {
+            let mut public_keys = Vec::new();
+            storage.for_each(|peer| {
+                if peer.is_banned() && predicate(&peer) {
+                    public_keys.push(peer.public_key);
+                }
+                IterationResult::Continue
+            })?;
+
+            for public_key in &public_keys {
+                let node_id = storage.unban(public_key)?;
+                self.counters.inc_updates();
+                self.publish_change(|| Some(PeerStoreChange::new(node_id, PeerStoreChangeKind::Unbanned)));
+            }
+
+            Ok(public_keys.len())
+        })
+        .await
+    }
+
+    /// Ban the peer for a length of time specified by the duration
+    pub async fn ban_for(&self, public_key: &CommsPublicKey, duration: Duration) -> Result<NodeId, PeerManagerError> {
+        let node_id = self
+            .write_storage_guarded(|storage| storage.ban_for(public_key, duration))
+            .await?;
+        self.counters.inc_updates();
+        self.publish_change(|| {
+            Some(PeerStoreChange::new(
+                node_id.clone(),
+                PeerStoreChangeKind::Banned(duration),
+            ))
+        });
+        Ok(node_id)
+    }
+
+    /// Ban the peer for `base * 2^n`, capped at `max`, where n is the number of previous escalating bans of the peer,
+    /// so that repeat offenders receive progressively longer bans. The ban count decays with good behaviour (see
+    /// `Peer::ban_count`). Returns the node id of the peer and the applied ban duration.
+    pub async fn ban_escalating(
+        &self,
+        public_key: &CommsPublicKey,
+        base: Duration,
+        max: Duration,
+    ) -> Result<(NodeId, Duration), PeerManagerError>
+    {
+        let (node_id, duration) = self
+            .write_storage_guarded(|storage| {
+                let mut peer = storage.find_by_public_key(public_key)?;
+                let duration = peer.ban_escalating(base, max);
+                let node_id = peer.node_id.clone();
+                storage.add_peer(peer)?;
+                Ok((node_id, duration))
+            })
+            .await?;
+        self.counters.inc_updates();
+        self.publish_change(|| {
+            Some(PeerStoreChange::new(
+                node_id.clone(),
+                PeerStoreChangeKind::Banned(duration),
+            ))
+        });
+        Ok((node_id, duration))
+    }
+
+    /// Extend the ban of the peer by `additional` time. If the peer is banned, the extension is added to the remaining
+    /// ban, otherwise the peer is banned for `additional` from now, so a ban is only ever lengthened. The published
+    /// `Banned` change contains the remaining duration of the resulting ban.
+    pub async fn extend_ban(
+        &self,
+        public_key: &CommsPublicKey,
+        additional: Duration,
+    ) -> Result<NodeId, PeerManagerError>
+    {
+        let (node_id, duration) = self
+            .write_storage_guarded(|storage| {
+                let mut peer = storage.find_by_public_key(public_key)?;
+                let duration = peer.extend_ban(additional);
+                let node_id = peer.node_id.clone();
+                storage.add_peer(peer)?;
+                Ok((node_id, duration))
+            })
+            .await?;
+        self.counters.inc_updates();
+        self.publish_change(|| {
+            Some(PeerStoreChange::new(
+                node_id.clone(),
+                PeerStoreChangeKind::Banned(duration),
+            ))
+        });
+        Ok(node_id)
+    }
+
+    /// Add the given feature bits to the peer's features and return the resulting features
+    pub async fn add_features(
+        &self,
+        node_id: &NodeId,
+        features: PeerFeatures,
+    ) -> Result<PeerFeatures, PeerManagerError>
+    {
+        self.modify_features(node_id, |current| current | features).await
+    }
+
+    /// Remove the given feature bits from the peer's features and return the resulting features
+    pub async fn remove_features(
+        &self,
+        node_id: &NodeId,
+        features: PeerFeatures,
+    ) -> Result<PeerFeatures, PeerManagerError>
+    {
+        self.modify_features(node_id, |current| current - features).await
+    }
+
+    async fn modify_features<F>(&self, node_id: &NodeId, modify: F) -> Result<PeerFeatures, PeerManagerError>
+    where F: FnOnce(PeerFeatures) -> PeerFeatures {
+        self.write_storage_guarded(|storage| {
+            let peer = storage.find_by_node_id(node_id)?;
+            let features = modify(peer.features);
+            if features != peer.features {
+                storage.update_peer(
+                    &peer.public_key,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some(features),
+                    None,
+                    None,
+                )?;
+                self.counters.inc_updates();
+                self.publish_change(|| {
+                    Some(PeerStoreChange::new(
+                        node_id.clone(),
+                        PeerStoreChangeKind::FeaturesChanged(features),
+                    ))
+                });
+            }
+            Ok(features)
+        })
+        .await
+    }
+
+    /// Returns a `BanRecord` for every banned peer, with the remaining duration of each ban
+    pub async fn export_bans(&self) -> Result<Vec<BanRecord>, PeerManagerError> {
+        let mut records = Vec::new();
+        self.read_storage().await?.for_each(|peer| {
+            if let Some(remaining) = peer.ban_remaining() {
+                records.push(BanRecord::new(peer.public_key, remaining, None));
+            }
+            IterationResult::Continue
+        })?;
+        Ok(records)
+    }
+
+    /// Applies ban records, typically exported from another node. A record only ever extends a peer's ban, so the
+    /// resulting ban lasts for the longer of the local ban and the record's duration. Seed peers and peers that are not
+    /// known locally are never banned. If `trust_source` is false, records only extend existing local bans and never
+    /// ban a peer that is not already banned. Returns the number of peers whose ban was applied or extended.
+    pub async fn apply_ban_records(
+        &self,
+        records: &[BanRecord],
+        trust_source: bool,
+    ) -> Result<usize, PeerManagerError>
+    {
+        self.write_storage_guarded(|storage| {
+            let mut num_applied = 0;
+            for record in records {
+                let peer = match storage.find_by_public_key(&record.public_key) {
+                    Ok(peer) => peer,
+                    Err(PeerManagerError::PeerNotFoundError) => continue,
+                    Err(err) => return Err(err),
+                };
+                if peer.is_seed() {
+                    continue;
+                }
+                match peer.ban_remaining() {
+                    Some(remaining) if remaining >= record.duration => continue,
+                    None if !trust_source => continue,
+                    _ => {},
+                }
+
+                let node_id = storage.ban_for(&record.public_key, record.duration)?;
+                self.counters.inc_updates();
+                self.publish_change(|| {
+                    Some(PeerStoreChange::new(
+                        node_id,
+                        PeerStoreChangeKind::Banned(record.duration),
+                    ))
+                });
+                num_applied += 1;
+            }
+            Ok(num_applied)
+        })
+        .await
+    }
+
+    /// Ban every peer with an address in the subnet given by the IP address of `addr` and `prefix_len` (e.g.
+    /// `/ip4/1.2.3.4/tcp/18000` with a prefix length of 24 bans all peers in 1.2.3.0/24) for the given duration.
+    /// Returns the number of peers that were banned.
+    pub async fn ban_subnet(
+        &self,
+        addr: &Multiaddr,
+        prefix_len: u8,
+        duration: Duration,
+    ) -> Result<usize, PeerManagerError>
+    {
+        let ip = multiaddr_to_ip(addr).ok_or_else(|| PeerManagerError::InvalidSubnet)?;
+        let subnet = IpInet::new(ip, prefix_len)
+            .map_err(|_| PeerManagerError::InvalidSubnet)?
+            .network();
+        let node_ids = self
+            .write_storage_guarded(|storage| storage.ban_subnet(&subnet, duration))
+            .await?;
+        self.counters.add_updates(node_ids.len());
+        for node_id in &node_ids {
+            self.publish_change(|| {
+                Some(PeerStoreChange::new(
+                    node_id.clone(),
+                    PeerStoreChangeKind::Banned(duration),
+                ))
+            });
+        }
+        Ok(node_ids.len())
+    }
+
+    /// Marks each known peer in `node_ids` as seen now and clears its offline flag (see `Peer::touch`) under a single
+    /// write lock. Unknown node ids are ignored. Returns the number of peers that were updated.
+    pub async fn touch_many(&self, node_ids: &[NodeId]) -> Result<usize, PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            let mut num_updated = 0;
+            for node_id in node_ids {
+                let mut peer = match storage.find_by_node_id(node_id) {
+                    Ok(peer) => peer,
+                    Err(PeerManagerError::PeerNotFoundError) => continue,
+                    Err(err) => return Err(err),
+                };
+                peer.touch();
+                storage.add_peer(peer)?;
+                self.counters.inc_updates();
+                self.publish_peer_updated(storage, node_id);
+                num_updated += 1;
+            }
+            Ok(num_updated)
+        })
+        .await
+    }
+
+    /// Changes the offline flag bit of the peer
+    pub async fn set_offline(&self, public_key: &CommsPublicKey, is_offline: bool) -> Result<NodeId, PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            let node_id = storage.set_offline(public_key, is_offline)?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, &node_id);
+            Ok(node_id)
+        })
+        .await
+    }
+
+    /// Adds a new net address to the peer if it doesn't yet exist
+    pub async fn add_net_address(&self, node_id: &NodeId, net_address: &Multiaddr) -> Result<(), PeerManagerError> {
+        self.write_storage_guarded(|storage| {
+            storage.add_net_address(node_id, net_address)?;
+            self.counters.inc_updates();
+            self.publish_peer_updated(storage, node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn update_each<F>(&self, mut f: F) -> Result<usize, PeerManagerError>
+    where F: FnMut(Peer) -> Option<Peer> {
+        self.write_storage_guarded(|storage| {
+            let mut peers_to_update = Vec::new();
+            storage.for_each(|peer| {
+                if let Some(peer) = (f)(peer) {
+                    peers_to_update.push(peer);
+                }
+                IterationResult::Continue
+            })?;
+
+            let mut updated_node_ids = Vec::with_capacity(peers_to_update.len());
+            for p in peers_to_update {
+                updated_node_ids.push(p.node_id.clone());
+                storage.add_peer(p)?;
+            }
+
+            let updated_count = updated_node_ids.len();
+            for node_id in &updated_node_ids {
+                self.publish_peer_updated(storage, node_id);
+            }
+            self.counters.add_updates(updated_count);
+
+            Ok(updated_count)
+        })
+        .await
+    }
+
+    /// Clears the offline flag of all peers that have been marked as offline for longer than `older_than`, so that
+    /// they are retried. Returns the number of peers that were updated.
+    pub async fn clear_stale_offline_flags(&self, older_than: Duration) -> Result<usize, PeerManagerError> {
+        self.update_each(|mut peer| {
+            if peer.is_offline_expired(older_than) {
+                peer.set_offline(false);
+                Some(peer)
+            } else {
+                None
+            }
+        })
+        .await
+    }
+
+    /// Returns true if a panic occurred during a mutation of the peer store. While this is the case, all operations
+    /// return `PeerManagerError::NeedsRepair` until `repair_integrity` is called.
+    pub fn needs_repair(&self) -> bool {
+        self.needs_repair.load(Ordering::SeqCst)
+    }
+
+    /// Rebuilds the peer store indexes from the underlying database and clears the `needs_repair` flag. This should be
+    /// called after a mutation has panicked, as the indexes may no longer be consistent with the database.
+    pub async fn repair_integrity(&self) -> Result<CompactionReport, PeerManagerError> {
+        let report = self.acquire_write().await.compact()?;
+        self.needs_repair.store(false, Ordering::SeqCst);
+        Ok(report)
+    }
+
+    /// Return some basic stats about the region around region_node_id
+    pub async fn get_region_stats<'a>(
+        &self,
+        region_node_id: &'a NodeId,
+        n: usize,
+        features: PeerFeatures,
+    ) -> Result<RegionStats<'a>, PeerManagerError>
+    {
+        self.read_storage().await?.get_region_stats(region_node_id, n, features)
+    }
+
+    pub async fn get_peer_features(&self, node_id: &NodeId) -> Result<PeerFeatures, PeerManagerError> {
+        // TODO: #sqliterefactor fetch the features with a sql query
+        let peer = self.find_by_node_id(node_id).await?;
+        Ok(peer.features)
+    }
+
+    fn check_integrity(&self) -> Result<(), PeerManagerError> {
+        if self.needs_repair() {
+            Err(PeerManagerError::NeedsRepair)
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn read_storage(&self) -> Result<RwLockReadGuard<'_, PeerStorage<PeerDatabase>>, PeerManagerError> {
+        self.check_integrity()?;
+        Ok(self.acquire_read().await)
+    }
+
+    /// Acquires the write lock and applies `mutation` to the peer storage. Every mutation of the peer store must go
+    /// through this function, so that a panic during the mutation flags the store as needing repair (see
+    /// `guard_mutation`).
+    async fn write_storage_guarded<T, F>(&self, mutation: F) -> Result<T, PeerManagerError>
+    where F: FnOnce(&mut PeerStorage<PeerDatabase>) -> Result<T, PeerManagerError> {
+        self.check_integrity()?;
+        let mut storage = self.acquire_write().await;
+        self.guard_mutation(|| mutation(&mut *storage))
+    }
+
+    async fn acquire_read(&self) -> RwLockReadGuard<'_, PeerStorage<PeerDatabase>> {
+        #[cfg(feature = "peer-store-lock-metrics")]
+        let timer = Instant::now();
+        let guard = self.peer_storage.read().await;
+        #[cfg(feature = "peer-store-lock-metrics")]
+        self.lock_wait_counters.record_read(timer.elapsed());
+        guard
+    }
+
+    async fn acquire_write(&self) -> RwLockWriteGuard<'_, PeerStorage<PeerDatabase>> {
+        #[cfg(feature = "peer-store-lock-metrics")]
+        let timer = Instant::now();
+        let guard = self.peer_storage.write().await;
+        #[cfg(feature = "peer-store-lock-metrics")]
+        self.lock_wait_counters.record_write(timer.elapsed());
+        guard
+    }
+
+    /// Runs a mutation of the peer store. If the mutation panics, the panic is caught and the store is flagged as
+    /// needing repair, because the panic may have left the indexes half updated.
+    fn guard_mutation<T, F>(&self, mutation: F) -> Result<T, PeerManagerError>
+    where F: FnOnce() -> Result<T, PeerManagerError> {
+        match panic::catch_unwind(AssertUnwindSafe(mutation)) {
+            Ok(result) => result,
+            Err(_) => {
+                self.needs_repair.store(true, Ordering::SeqCst);
+                Err(PeerManagerError::NeedsRepair)
+            },
+        }
+    }
+
+    fn check_not_self(&self, peer: &Peer) -> Result<(), PeerManagerError> {
+        match self.local_public_key.read()?.as_ref() {
+            Some(public_key) if *public_key == peer.public_key => Err(PeerManagerError::CannotAddSelf),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the admission policy, if any, to a peer that is about to be added
+    fn admit(&self, peer: &mut Peer) -> Result<(), PeerManagerError> {
+        let decision = match self.admission_policy.read()?.as_ref() {
+            Some(policy) => policy(peer),
+            None => AdmissionDecision::Accept,
+        };
+        match decision {
+            AdmissionDecision::Accept => Ok(()),
+            AdmissionDecision::Reject => Err(PeerManagerError::AdmissionRejected),
+            AdmissionDecision::AcceptQuarantined => {
+                peer.quarantine_for(PEER_ADMISSION_QUARANTINE_PERIOD);
+                Ok(())
+            },
+        }
+    }
+
+    fn validate_node_id(peer: &Peer) -> Result<(), PeerManagerError> {
+        let expected_node_id = NodeId::from_key(&peer.public_key).map_err(|_| PeerManagerError::NodeIdMismatch)?;
+        if expected_node_id == peer.node_id {
+            Ok(())
+        } else {
+            Err(PeerManagerError::NodeIdMismatch)
+        }
+    }
+
+    /// Publishes a change to the change log. The change is only constructed if there are subscribers, so this is
+    /// very cheap when the change log is not in use.
+    fn publish_change<F>(&self, change: F)
+    where F: FnOnce() -> Option<PeerStoreChange> {
+        if self.change_log.receiver_count() == 0 {
+            return;
+        }
+        if let Some(change) = change() {
+            // Sending only fails if all subscribers have since been dropped
+            let _ = self.change_log.send(Arc::new(change));
+        }
+    }
+
+    fn publish_peer_updated(&self, storage: &PeerStorage<PeerDatabase>, node_id: &NodeId) {
+        self.publish_change(|| {
+            let peer = storage.find_by_node_id(node_id).ok()?;
+            Some(PeerStoreChange::new(
+                node_id.clone(),
+                PeerStoreChangeKind::Updated(Box::new(peer)),
+            ))
+        });
+    }
+
+    fn record_lookup<T>(&self, result: &Result<T, PeerManagerError>) {
+        match result {
+            Ok(_) => self.counters.record_lookup(true),
+            Err(PeerManagerError::PeerNotFoundError) => self.counters.record_lookup(false),
+            Err(_) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        consts::{PEER_DISCONNECT_QUARANTINE_THRESHOLD, PEER_OFFLINE_COOLDOWN_PERIOD, PEER_OFFLINE_EXPIRY},
         net_address::MultiaddressesWithStats,
         peer_manager::{
             node_id::NodeId,
@@ -358,9 +1677,13 @@ mod test {
             PeerFeatures,
         },
     };
+    use chrono::Utc;
+    use futures::future;
     use rand::rngs::OsRng;
-    use tari_crypto::{keys::PublicKey, ristretto::RistrettoPublicKey};
+    use std::{collections::HashSet, sync::atomic::AtomicUsize};
+    use tari_crypto::{keys::PublicKey, ristretto::RistrettoPublicKey, tari_utilities::ByteArray};
     use tari_storage::HashmapDatabase;
+    use tari_test_utils::unpack_enum;
 
     fn create_test_peer(ban_flag: bool, features: PeerFeatures) -> Peer {
         let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut OsRng);
@@ -605,4 +1928,1923 @@ mod test {
         assert_eq!(peer.is_offline(), false);
         assert_eq!(peer.connection_stats.failed_attempts(), 0);
     }
+
+    #[tokio_macros::test_basic]
+    async fn add_or_update_online_peer_concurrent() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let mut changes = peer_manager.subscribe_changes();
+
+        let results = future::join_all((0..10).map(|_| {
+            peer_manager.add_or_update_online_peer(&peer.public_key, peer.node_id.clone(), vec![], peer.features)
+        }))
+        .await;
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(peer_manager.all().await.unwrap().len(), 1);
+
+        // The peer is added exactly once and every other call updates it
+        let mut num_added = 0;
+        let mut num_updated = 0;
+        while let Ok(change) = changes.try_recv() {
+            match change.kind {
+                PeerStoreChangeKind::Added(_) => num_added += 1,
+                PeerStoreChangeKind::Updated(_) => num_updated += 1,
+                _ => {},
+            }
+        }
+        assert_eq!(num_added, 1);
+        assert_eq!(num_updated, 9);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn operation_metrics() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        assert_eq!(peer_manager.operation_metrics(), PeerStoreMetrics::default());
+
+        let peer1 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let peer2 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer1.clone()).await.unwrap();
+        peer_manager.add_peer(peer2.clone()).await.unwrap();
+
+        peer_manager.find_by_node_id(&peer1.node_id).await.unwrap();
+        assert!(peer_manager.exists(&peer2.public_key).await);
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager
+            .find_by_public_key(&unknown_peer.public_key)
+            .await
+            .unwrap_err();
+
+        peer_manager.set_last_connect_failed(&peer1.node_id).await.unwrap();
+        peer_manager
+            .ban_for(&peer2.public_key, Duration::from_secs(100))
+            .await
+            .unwrap();
+        peer_manager.delete_peer(&peer1.node_id).await.unwrap();
+
+        let metrics = peer_manager.operation_metrics();
+        assert_eq!(metrics.adds, 2);
+        assert_eq!(metrics.updates, 2);
+        assert_eq!(metrics.deletes, 1);
+        assert_eq!(metrics.lookups, 3);
+        assert_eq!(metrics.cache_hits, 2);
+        assert_eq!(metrics.cache_misses, 1);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn peers_supporting_protocol_version() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let protocols = [
+            vec![ProtocolId::from_static(b"/tari/messaging/1.0.0")],
+            vec![ProtocolId::from_static(b"/tari/messaging/2.0.0")],
+            vec![
+                ProtocolId::from_static(b"/tari/identity/3.0.0"),
+                ProtocolId::from_static(b"/tari/messaging/3.1.0"),
+            ],
+            vec![ProtocolId::from_static(b"/tari/identity/2.0.0")],
+            vec![ProtocolId::from_static(b"/tari/messaging/unversioned")],
+        ];
+        let mut test_peers = Vec::new();
+        for supported_protocols in protocols.iter() {
+            let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+            peer.supported_protocols = supported_protocols.clone();
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+            test_peers.push(peer);
+        }
+
+        let peers = peer_manager
+            .peers_supporting_protocol_version(b"/tari/messaging", 2)
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 2);
+        assert!(peers.iter().any(|p| p.node_id == test_peers[1].node_id));
+        assert!(peers.iter().any(|p| p.node_id == test_peers[2].node_id));
+
+        let peers = peer_manager
+            .peers_supporting_protocol_version(b"/tari/identity", 3)
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].node_id, test_peers[2].node_id);
+
+        let peers = peer_manager
+            .peers_supporting_protocol_version(b"/tari/unknown", 0)
+            .await
+            .unwrap();
+        assert!(peers.is_empty());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn delete_by_public_key() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer1 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let peer2 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer1.clone()).await.unwrap();
+        peer_manager.add_peer(peer2.clone()).await.unwrap();
+
+        peer_manager.delete_by_public_key(&peer1.public_key).await.unwrap();
+        assert!(!peer_manager.exists(&peer1.public_key).await);
+        assert!(!peer_manager.exists_node_id(&peer1.node_id).await);
+        assert!(peer_manager.exists(&peer2.public_key).await);
+
+        let err = peer_manager.delete_by_public_key(&peer1.public_key).await.unwrap_err();
+        assert!(err.is_peer_not_found());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn try_closest_peers() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        for _ in 0..5 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+        let node_id = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE).node_id;
+
+        let write_lock = peer_manager.peer_storage.write().await;
+        let err = peer_manager
+            .try_closest_peers(&node_id, 3, &[], None, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(match err {
+            PeerManagerError::LockTimeout => true,
+            _ => false,
+        });
+        drop(write_lock);
+
+        let peers = peer_manager
+            .try_closest_peers(&node_id, 3, &[], None, Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 3);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn any_connectable() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        assert!(!peer_manager.any_connectable(None).await.unwrap());
+
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        let mut offline_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_peer.set_offline(true);
+        peer_manager.add_peer(offline_peer).await.unwrap();
+        assert!(!peer_manager.any_connectable(None).await.unwrap());
+
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+        assert!(peer_manager.any_connectable(None).await.unwrap());
+        assert!(peer_manager
+            .any_connectable(Some(PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap());
+        assert!(!peer_manager
+            .any_connectable(Some(PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn delete_where() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut offline_seed = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_seed.flags = PeerFlags::SEED;
+        offline_seed.set_offline(true);
+        peer_manager.add_peer(offline_seed.clone()).await.unwrap();
+
+        let mut offline_peers = Vec::new();
+        for _ in 0..3 {
+            let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+            peer.set_offline(true);
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+            offline_peers.push(peer);
+        }
+        let online_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(online_peer.clone()).await.unwrap();
+
+        let num_deleted = peer_manager.delete_where(|peer| peer.is_offline()).await.unwrap();
+        assert_eq!(num_deleted, 3);
+        for peer in &offline_peers {
+            assert!(!peer_manager.exists_node_id(&peer.node_id).await);
+        }
+        assert!(peer_manager.exists_node_id(&offline_seed.node_id).await);
+        assert!(peer_manager.exists_node_id(&online_peer.node_id).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn has_addresses() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer.addresses = Default::default();
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+
+        assert!(!peer_manager.has_addresses(&peer.node_id).await.unwrap());
+        assert!(!peer_manager.any_connectable(None).await.unwrap());
+
+        peer_manager
+            .add_net_address(&peer.node_id, &"/ip4/1.2.3.4/tcp/8000".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(peer_manager.has_addresses(&peer.node_id).await.unwrap());
+        assert!(peer_manager.any_connectable(None).await.unwrap());
+
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let err = peer_manager.has_addresses(&unknown_peer.node_id).await.unwrap_err();
+        assert!(err.is_peer_not_found());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn subscribe_changes() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut changes = peer_manager.subscribe_changes();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.node_id, peer.node_id);
+        unpack_enum!(PeerStoreChangeKind::Added(added) = &change.kind);
+        assert_eq!(added.public_key, peer.public_key);
+
+        peer_manager
+            .update_peer(
+                &peer.public_key,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(PeerFeatures::COMMUNICATION_CLIENT),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.node_id, peer.node_id);
+        unpack_enum!(PeerStoreChangeKind::Updated(updated) = &change.kind);
+        assert_eq!(updated.features, PeerFeatures::COMMUNICATION_CLIENT);
+
+        peer_manager
+            .ban_for(&peer.public_key, Duration::from_secs(100))
+            .await
+            .unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.kind, PeerStoreChangeKind::Banned(Duration::from_secs(100)));
+
+        peer_manager.unban(&peer.public_key).await.unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.kind, PeerStoreChangeKind::Unbanned);
+
+        peer_manager.delete_peer(&peer.node_id).await.unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.node_id, peer.node_id);
+        assert_eq!(change.kind, PeerStoreChangeKind::Deleted);
+
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn ban_subnet() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let create_peer_with_address = |addr: &str| {
+            let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+            peer.addresses = MultiaddressesWithStats::from(addr.parse::<Multiaddr>().unwrap());
+            peer
+        };
+        let in_subnet = vec![
+            create_peer_with_address("/ip4/10.1.2.1/tcp/8000"),
+            create_peer_with_address("/ip4/10.1.2.254/tcp/8000"),
+        ];
+        let outside_subnet = vec![
+            create_peer_with_address("/ip4/10.1.3.1/tcp/8000"),
+            create_peer_with_address("/ip6/::1/tcp/8000"),
+            create_peer_with_address("/dns4/localhost/tcp/8000"),
+        ];
+        for p in in_subnet.iter().chain(outside_subnet.iter()) {
+            peer_manager.add_peer(p.clone()).await.unwrap();
+        }
+
+        let num_banned = peer_manager
+            .ban_subnet(
+                &"/ip4/10.1.2.100/tcp/18000".parse().unwrap(),
+                24,
+                Duration::from_secs(100),
+            )
+            .await
+            .unwrap();
+        assert_eq!(num_banned, 2);
+        for p in &in_subnet {
+            assert!(peer_manager.find_by_node_id(&p.node_id).await.unwrap().is_banned());
+        }
+        for p in &outside_subnet {
+            assert!(!peer_manager.find_by_node_id(&p.node_id).await.unwrap().is_banned());
+        }
+
+        let err = peer_manager
+            .ban_subnet(
+                &"/ip4/10.1.2.100/tcp/18000".parse().unwrap(),
+                33,
+                Duration::from_secs(100),
+            )
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::InvalidSubnet = err);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn nearest_with_backup() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let target = create_test_peer(false, Default::default()).node_id;
+        let features = Some(PeerFeatures::COMMUNICATION_NODE);
+
+        // Ineligible peers are never selected
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+        assert!(peer_manager
+            .nearest_with_backup(&target, features)
+            .await
+            .unwrap()
+            .is_none());
+
+        let peer1 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer1.clone()).await.unwrap();
+        let (primary, backup) = peer_manager
+            .nearest_with_backup(&target, features)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(primary.node_id, peer1.node_id);
+        assert!(backup.is_none());
+
+        let peer2 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer2.clone()).await.unwrap();
+        let (primary, backup) = peer_manager
+            .nearest_with_backup(&target, features)
+            .await
+            .unwrap()
+            .unwrap();
+        let backup = backup.unwrap();
+        assert!(target.distance(&primary.node_id) < target.distance(&backup.node_id));
+        let mut selected = vec![primary.node_id, backup.node_id];
+        selected.sort();
+        let mut expected = vec![peer1.node_id, peer2.node_id];
+        expected.sort();
+        assert_eq!(selected, expected);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn peers_ranked_by_distance() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let key = create_test_peer(false, Default::default()).node_id;
+        let mut eligible_peers = (0..10)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for p in &eligible_peers {
+            peer_manager.add_peer(p.clone()).await.unwrap();
+        }
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+        eligible_peers.sort_by(|a, b| key.distance(&a.node_id).cmp(&key.distance(&b.node_id)));
+
+        let features = Some(PeerFeatures::COMMUNICATION_NODE);
+        let all = peer_manager
+            .peers_ranked_by_distance(&key, 0, 100, features)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 10);
+        for ((peer, dist), expected) in all.iter().zip(&eligible_peers) {
+            assert_eq!(peer.node_id, expected.node_id);
+            assert_eq!(*dist, key.distance(&expected.node_id));
+        }
+
+        let page = peer_manager
+            .peers_ranked_by_distance(&key, 4, 3, features)
+            .await
+            .unwrap();
+        assert_eq!(
+            page.iter().map(|(p, _)| &p.node_id).collect::<Vec<_>>(),
+            eligible_peers[4..7].iter().map(|p| &p.node_id).collect::<Vec<_>>()
+        );
+
+        let last_page = peer_manager
+            .peers_ranked_by_distance(&key, 8, 3, features)
+            .await
+            .unwrap();
+        assert_eq!(last_page.len(), 2);
+        assert_eq!(last_page[1].0.node_id, eligible_peers[9].node_id);
+
+        let past_end = peer_manager
+            .peers_ranked_by_distance(&key, 10, 3, features)
+            .await
+            .unwrap();
+        assert!(past_end.is_empty());
+
+        let all_features = peer_manager.peers_ranked_by_distance(&key, 0, 100, None).await.unwrap();
+        assert_eq!(all_features.len(), 11);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn unban_where() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let short_bans = (0..3)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        let long_bans = (0..2)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        let not_banned = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        for p in short_bans.iter().chain(long_bans.iter()).chain(Some(&not_banned)) {
+            peer_manager.add_peer(p.clone()).await.unwrap();
+        }
+        for p in &short_bans {
+            peer_manager
+                .ban_for(&p.public_key, Duration::from_secs(60))
+                .await
+                .unwrap();
+        }
+        for p in &long_bans {
+            peer_manager
+                .ban_for(&p.public_key, Duration::from_secs(24 * 60 * 60))
+                .await
+                .unwrap();
+        }
+
+        let cutoff = Utc::now().naive_utc() + chrono::Duration::hours(1);
+        let num_unbanned = peer_manager
+            .unban_where(|peer| peer.banned_until().map(|until| *until < cutoff).unwrap_or(false))
+            .await
+            .unwrap();
+        assert_eq!(num_unbanned, 3);
+        for p in &short_bans {
+            assert!(!peer_manager.find_by_node_id(&p.node_id).await.unwrap().is_banned());
+        }
+        for p in &long_bans {
+            assert!(peer_manager.find_by_node_id(&p.node_id).await.unwrap().is_banned());
+        }
+
+        // Peers that are not banned are never counted
+        let num_unbanned = peer_manager.unban_where(|_| true).await.unwrap();
+        assert_eq!(num_unbanned, 2);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn exists_node_ids() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let known_peer1 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let known_peer2 = create_test_peer(true, PeerFeatures::COMMUNICATION_CLIENT);
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(known_peer1.clone()).await.unwrap();
+        peer_manager.add_peer(known_peer2.clone()).await.unwrap();
+
+        let exists = peer_manager
+            .exists_node_ids(&[
+                unknown_peer.node_id.clone(),
+                known_peer1.node_id.clone(),
+                known_peer2.node_id.clone(),
+                unknown_peer.node_id.clone(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exists, vec![false, true, true, false]);
+        assert!(peer_manager.exists_node_ids(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn export_import_round_trip() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut peers = (0..10)
+            .map(|i| create_test_peer(i % 3 == 0, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for p in &peers {
+            peer_manager.add_peer(p.clone()).await.unwrap();
+        }
+
+        let mut buf = Vec::new();
+        let num_exported = peer_manager.export_to_writer(&mut buf).await.unwrap();
+        assert_eq!(num_exported, 10);
+        assert_eq!(buf.iter().filter(|b| **b == b'\n').count(), 10);
+
+        let restored_peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let num_imported = restored_peer_manager.import_from_reader(buf.as_slice()).await.unwrap();
+        assert_eq!(num_imported, 10);
+
+        let mut restored_peers = restored_peer_manager.all().await.unwrap();
+        restored_peers.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        peers.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        assert_eq!(restored_peers.len(), peers.len());
+        for (restored, expected) in restored_peers.iter().zip(&peers) {
+            assert_eq!(restored.public_key, expected.public_key);
+            assert_eq!(restored.node_id, expected.node_id);
+            assert_eq!(restored.addresses, expected.addresses);
+            assert_eq!(restored.features, expected.features);
+            assert_eq!(restored.banned_until, expected.banned_until);
+        }
+
+        let err = restored_peer_manager
+            .import_from_reader(&b"not a peer\n"[..])
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::SerializationError(_err) = err);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn update_peer_returning() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+
+        let address = "/ip4/5.6.7.8/tcp/8000".parse::<Multiaddr>().unwrap();
+        let updated_peer = peer_manager
+            .update_peer_returning(
+                &peer.public_key,
+                None,
+                Some(vec![address.clone()]),
+                None,
+                Some(Some(Duration::from_secs(100))),
+                None,
+                Some(PeerFeatures::COMMUNICATION_CLIENT),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated_peer.node_id, peer.node_id);
+        assert_eq!(updated_peer.addresses.address_iter().collect::<Vec<_>>(), vec![
+            &address
+        ]);
+        assert!(updated_peer.is_banned());
+        assert_eq!(updated_peer.features, PeerFeatures::COMMUNICATION_CLIENT);
+        assert_eq!(
+            updated_peer,
+            peer_manager.find_by_public_key(&peer.public_key).await.unwrap()
+        );
+
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let err = peer_manager
+            .update_peer_returning(&unknown_peer.public_key, None, None, None, None, None, None, None, None)
+            .await
+            .unwrap_err();
+        assert!(err.is_peer_not_found());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn export_and_apply_ban_records() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let short_ban = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let long_ban = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let not_banned = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let mut seed = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        seed.flags = PeerFlags::SEED;
+        let unknown = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        for p in &[&short_ban, &long_ban, &not_banned, &seed] {
+            peer_manager.add_peer((*p).clone()).await.unwrap();
+        }
+        peer_manager
+            .ban_for(&short_ban.public_key, Duration::from_secs(100))
+            .await
+            .unwrap();
+        peer_manager
+            .ban_for(&long_ban.public_key, Duration::from_secs(1000))
+            .await
+            .unwrap();
+
+        let mut exported = peer_manager.export_bans().await.unwrap();
+        exported.sort_by_key(|r| r.duration);
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0].public_key, short_ban.public_key);
+        assert_eq!(exported[1].public_key, long_ban.public_key);
+
+        let records = [&short_ban, &long_ban, &not_banned, &seed, &unknown]
+            .iter()
+            .map(|p| BanRecord::new(p.public_key.clone(), Duration::from_secs(500), None))
+            .collect::<Vec<_>>();
+
+        // Only the existing short ban is extended when the source is not trusted
+        let num_applied = peer_manager.apply_ban_records(&records, false).await.unwrap();
+        assert_eq!(num_applied, 1);
+        let ban_remaining = |peer: Peer| peer.ban_remaining().unwrap_or_default();
+        let remaining = ban_remaining(peer_manager.find_by_node_id(&short_ban.node_id).await.unwrap());
+        assert!(remaining > Duration::from_secs(400) && remaining <= Duration::from_secs(500));
+        let remaining = ban_remaining(peer_manager.find_by_node_id(&long_ban.node_id).await.unwrap());
+        assert!(remaining > Duration::from_secs(900));
+        assert!(!peer_manager
+            .find_by_node_id(&not_banned.node_id)
+            .await
+            .unwrap()
+            .is_banned());
+
+        // A trusted source may ban peers that are not banned locally, but never a seed peer
+        let num_applied = peer_manager.apply_ban_records(&records[1..], true).await.unwrap();
+        assert_eq!(num_applied, 1);
+        let remaining = ban_remaining(peer_manager.find_by_node_id(&not_banned.node_id).await.unwrap());
+        assert!(remaining > Duration::from_secs(400) && remaining <= Duration::from_secs(500));
+        let remaining = ban_remaining(peer_manager.find_by_node_id(&long_ban.node_id).await.unwrap());
+        assert!(remaining > Duration::from_secs(900));
+        assert!(!peer_manager.find_by_node_id(&seed.node_id).await.unwrap().is_banned());
+        assert!(!peer_manager.exists(&unknown.public_key).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn add_peer_node_id_mismatch() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let other_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer.node_id = other_peer.node_id.clone();
+
+        let err = peer_manager.add_peer(peer.clone()).await.unwrap_err();
+        unpack_enum!(PeerManagerError::NodeIdMismatch = err);
+        assert!(!peer_manager.exists(&peer.public_key).await);
+        assert!(!peer_manager.exists_node_id(&other_peer.node_id).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn custom_distance_metric() {
+        /// Inverts the XOR metric so that the furthest peers are considered the closest
+        struct InverseXorDistance;
+        impl DistanceMetric for InverseXorDistance {
+            fn distance(&self, a: &NodeId, b: &NodeId) -> NodeDistance {
+                let inverted = a.distance(b).as_bytes().iter().map(|byte| !byte).collect::<Vec<_>>();
+                NodeDistance::from_bytes(&inverted).unwrap()
+            }
+        }
+
+        let n = 5;
+        let peer_manager =
+            PeerManager::new_with_distance_metric(HashmapDatabase::new(), Arc::new(InverseXorDistance)).unwrap();
+        let region_node_id = create_test_peer(false, Default::default()).node_id;
+        let mut test_peers = (0..20)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for p in &test_peers {
+            peer_manager.add_peer(p.clone()).await.unwrap();
+        }
+
+        let closest = peer_manager
+            .closest_peers(&region_node_id, n, &[], Some(PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        // The "closest" peers are the furthest by XOR distance
+        test_peers.sort_by(|a, b| {
+            region_node_id
+                .distance(&b.node_id)
+                .cmp(&region_node_id.distance(&a.node_id))
+        });
+        assert_eq!(
+            closest.iter().map(|p| &p.node_id).collect::<Vec<_>>(),
+            test_peers.iter().take(n).map(|p| &p.node_id).collect::<Vec<_>>()
+        );
+
+        // The network region is also determined by the custom metric
+        let region_stats = peer_manager
+            .get_region_stats(&region_node_id, n, PeerFeatures::COMMUNICATION_NODE)
+            .await
+            .unwrap();
+        assert!(test_peers[..n].iter().all(|p| region_stats.in_region(&p.node_id)));
+        assert!(!test_peers[n..].iter().any(|p| region_stats.in_region(&p.node_id)));
+    }
+
+    #[tokio_macros::test_basic]
+    async fn needs_repair_after_panicked_mutation() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        assert!(!peer_manager.needs_repair());
+
+        let err = peer_manager
+            .update_each(|_| -> Option<Peer> { panic!("injected panic") })
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::NeedsRepair = err);
+        assert!(peer_manager.needs_repair());
+
+        let err = peer_manager.find_by_node_id(&peer.node_id).await.unwrap_err();
+        unpack_enum!(PeerManagerError::NeedsRepair = err);
+        let err = peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::NeedsRepair = err);
+
+        peer_manager.repair_integrity().await.unwrap();
+        assert!(!peer_manager.needs_repair());
+        let found = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        assert_eq!(found.public_key, peer.public_key);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn every_mutation_is_guarded() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+
+        let err = peer_manager
+            .delete_where(|_| -> bool { panic!("injected panic") })
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::NeedsRepair = err);
+        assert!(peer_manager.needs_repair());
+        peer_manager.repair_integrity().await.unwrap();
+
+        // The peer exists, so `make` is not called
+        let found = peer_manager
+            .find_or_create(&peer.public_key, || panic!("not called"))
+            .await
+            .unwrap();
+        assert_eq!(found.public_key, peer.public_key);
+
+        let unknown = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let err = peer_manager
+            .find_or_create(&unknown.public_key, || panic!("injected panic"))
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::NeedsRepair = err);
+        assert!(peer_manager.needs_repair());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn closest_peers_by_feature_groups() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        for _ in 0..10 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+                .await
+                .unwrap();
+        }
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+
+        let target = create_test_peer(false, Default::default()).node_id;
+        let groups = [PeerFeatures::COMMUNICATION_NODE, PeerFeatures::COMMUNICATION_CLIENT];
+        let closest = peer_manager
+            .closest_peers_by_feature_groups(&target, 3, &groups)
+            .await
+            .unwrap();
+        assert_eq!(closest.len(), 2);
+        for group in &groups {
+            let expected = peer_manager.closest_peers(&target, 3, &[], Some(*group)).await.unwrap();
+            assert_eq!(closest[group].len(), 3);
+            assert_eq!(closest[group], expected);
+        }
+    }
+
+    #[tokio_macros::test_basic]
+    async fn try_for_each() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        for _ in 0..10 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+
+        let mut num_visited = 0;
+        peer_manager
+            .try_for_each(|_| -> Result<_, PeerManagerError> {
+                num_visited += 1;
+                Ok(IterationResult::Continue)
+            })
+            .await
+            .unwrap();
+        assert_eq!(num_visited, 10);
+
+        let mut num_visited = 0;
+        let err = peer_manager
+            .try_for_each(|_| {
+                num_visited += 1;
+                if num_visited == 3 {
+                    return Err(PeerManagerError::SerializationError("write failed".to_string()));
+                }
+                Ok(IterationResult::Continue)
+            })
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::SerializationError(msg) = err);
+        assert_eq!(msg, "write failed");
+        assert_eq!(num_visited, 3);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn closest_eligible_peers() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let target = create_test_peer(false, Default::default()).node_id;
+        let mut peers = (0..10)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| target.distance(&a.node_id).cmp(&target.distance(&b.node_id)));
+        // The three nearest peers cannot be dialed
+        peers[0].ban_for(Duration::from_secs(1000));
+        peers[1].set_offline(true);
+        peers[2].addresses = Default::default();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+
+        let closest = peer_manager
+            .closest_eligible_peers(&target, 4, &[], Some(PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        assert_eq!(
+            closest.iter().map(|p| &p.node_id).collect::<Vec<_>>(),
+            peers[3..7].iter().map(|p| &p.node_id).collect::<Vec<_>>()
+        );
+
+        let closest = peer_manager
+            .closest_eligible_peers(&target, 4, &[peers[3].public_key.clone()], None)
+            .await
+            .unwrap();
+        assert_eq!(closest[0].node_id, peers[4].node_id);
+        assert_eq!(closest.len(), 4);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn ring_successors() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut peers = (0..10)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+        let key = create_test_peer(false, Default::default()).node_id;
+        peers.sort_by(|a, b| key.distance(&a.node_id).cmp(&key.distance(&b.node_id)));
+
+        let successors = peer_manager.ring_successors(&key, 3, None).await.unwrap();
+        assert_eq!(
+            successors.iter().map(|p| &p.node_id).collect::<Vec<_>>(),
+            peers[..3].iter().map(|p| &p.node_id).collect::<Vec<_>>()
+        );
+
+        // A peer's first successor is itself
+        let successors = peer_manager.ring_successors(&peers[5].node_id, 1, None).await.unwrap();
+        assert_eq!(successors[0].node_id, peers[5].node_id);
+
+        let successors = peer_manager.ring_successors(&key, 20, None).await.unwrap();
+        assert_eq!(successors.len(), 10);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn cannot_add_self() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let local_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.set_local_identity(local_peer.public_key.clone()).unwrap();
+
+        let err = peer_manager.add_peer(local_peer.clone()).await.unwrap_err();
+        unpack_enum!(PeerManagerError::CannotAddSelf = err);
+        assert!(!peer_manager.exists(&local_peer.public_key).await);
+
+        let other_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(other_peer.clone()).await.unwrap();
+        assert!(peer_manager.exists(&other_peer.public_key).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn stale_offline_flags() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut recently_offline = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        recently_offline.set_offline(true);
+        peer_manager.add_peer(recently_offline.clone()).await.unwrap();
+        assert!(!peer_manager.any_connectable(None).await.unwrap());
+
+        let mut long_offline = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        long_offline.offline_at = Some(Utc::now().naive_utc() - chrono::Duration::days(2));
+        peer_manager.add_peer(long_offline.clone()).await.unwrap();
+        // The long offline peer is worth retrying
+        assert!(peer_manager.any_connectable(None).await.unwrap());
+        let target = create_test_peer(false, Default::default()).node_id;
+        let eligible = peer_manager
+            .closest_eligible_peers(&target, 2, &[], None)
+            .await
+            .unwrap();
+        assert_eq!(eligible.len(), 1);
+        assert_eq!(eligible[0].node_id, long_offline.node_id);
+
+        let num_cleared = peer_manager
+            .clear_stale_offline_flags(Duration::from_secs(60 * 60))
+            .await
+            .unwrap();
+        assert_eq!(num_cleared, 1);
+        assert!(!peer_manager
+            .find_by_node_id(&long_offline.node_id)
+            .await
+            .unwrap()
+            .is_offline());
+        assert!(peer_manager
+            .find_by_node_id(&recently_offline.node_id)
+            .await
+            .unwrap()
+            .is_offline());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn offline_expiry() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut offline = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline.offline_at = Some(Utc::now().naive_utc() - chrono::Duration::hours(2));
+        peer_manager.add_peer(offline.clone()).await.unwrap();
+        let target = create_test_peer(false, Default::default()).node_id;
+
+        // Offline for less than the default expiry
+        assert!(peer_manager
+            .closest_peers(&target, 1, &[], None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(peer_manager.random_peers(1, vec![]).await.unwrap().is_empty());
+        assert!(!peer_manager.any_connectable(None).await.unwrap());
+        assert!(peer_manager.connectable_at(&offline.node_id).await.unwrap().is_some());
+
+        peer_manager
+            .set_offline_expiry(Duration::from_secs(60 * 60))
+            .await
+            .unwrap();
+        let closest = peer_manager.closest_peers(&target, 1, &[], None).await.unwrap();
+        assert_eq!(closest.len(), 1);
+        assert_eq!(closest[0].node_id, offline.node_id);
+        assert_eq!(peer_manager.random_peers(1, vec![]).await.unwrap().len(), 1);
+        assert!(peer_manager.any_connectable(None).await.unwrap());
+        assert!(peer_manager.connectable_at(&offline.node_id).await.unwrap().is_none());
+
+        assert_eq!(
+            peer_manager.offline_expiry().await.unwrap(),
+            Duration::from_secs(60 * 60)
+        );
+
+        peer_manager.set_offline_expiry(PEER_OFFLINE_EXPIRY).await.unwrap();
+        assert!(peer_manager
+            .closest_peers(&target, 1, &[], None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn connectable_at() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        assert!(peer_manager.connectable_at(&peer.node_id).await.unwrap().is_none());
+
+        peer_manager.set_last_connect_failed(&peer.node_id).await.unwrap();
+        peer_manager.set_last_connect_failed(&peer.node_id).await.unwrap();
+        let connectable_at = peer_manager.connectable_at(&peer.node_id).await.unwrap().unwrap();
+        assert!(connectable_at > Instant::now());
+        assert!(connectable_at <= Instant::now() + PEER_OFFLINE_COOLDOWN_PERIOD);
+
+        peer_manager.set_last_connect_success(&peer.node_id).await.unwrap();
+        assert!(peer_manager.connectable_at(&peer.node_id).await.unwrap().is_none());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn apply_connection_outcomes() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut offline_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_peer.set_offline(true);
+        let failing_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(offline_peer.clone()).await.unwrap();
+        peer_manager.add_peer(failing_peer.clone()).await.unwrap();
+
+        peer_manager
+            .apply_connection_outcomes(&[
+                (offline_peer.node_id.clone(), true),
+                (failing_peer.node_id.clone(), false),
+                (failing_peer.node_id.clone(), false),
+            ])
+            .await
+            .unwrap();
+
+        let peer = peer_manager.find_by_node_id(&offline_peer.node_id).await.unwrap();
+        assert!(!peer.is_offline());
+        assert!(peer.connection_stats.has_ever_connected());
+        assert_eq!(peer.connection_stats.failed_attempts(), 0);
+        let peer = peer_manager.find_by_node_id(&failing_peer.node_id).await.unwrap();
+        assert_eq!(peer.connection_stats.failed_attempts(), 2);
+        assert!(!peer.connection_stats.has_ever_connected());
+
+        // Nothing is applied if a peer does not exist
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let err = peer_manager
+            .apply_connection_outcomes(&[(failing_peer.node_id.clone(), true), (unknown_peer.node_id, true)])
+            .await
+            .unwrap_err();
+        assert!(err.is_peer_not_found());
+        let peer = peer_manager.find_by_node_id(&failing_peer.node_id).await.unwrap();
+        assert_eq!(peer.connection_stats.failed_attempts(), 2);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn set_alias() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+
+        let err = peer_manager.find_by_alias("seed-frankfurt").await.unwrap_err();
+        assert!(err.is_peer_not_found());
+
+        peer_manager
+            .set_alias(&peer.node_id, Some("seed-frankfurt".to_string()))
+            .await
+            .unwrap();
+        let found = peer_manager.find_by_alias("seed-frankfurt").await.unwrap();
+        assert_eq!(found.node_id, peer.node_id);
+        assert!(found.to_string().contains("seed-frankfurt"));
+
+        peer_manager.set_alias(&peer.node_id, None).await.unwrap();
+        let err = peer_manager.find_by_alias("seed-frankfurt").await.unwrap_err();
+        assert!(err.is_peer_not_found());
+        assert!(peer_manager
+            .find_by_node_id(&peer.node_id)
+            .await
+            .unwrap()
+            .alias
+            .is_none());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn record_peer_initiated_disconnect() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        let target = create_test_peer(false, Default::default()).node_id;
+
+        for _ in 0..PEER_DISCONNECT_QUARANTINE_THRESHOLD {
+            assert_eq!(
+                peer_manager
+                    .closest_eligible_peers(&target, 1, &[], None)
+                    .await
+                    .unwrap()
+                    .len(),
+                1
+            );
+            peer_manager
+                .record_peer_initiated_disconnect(&peer.node_id)
+                .await
+                .unwrap();
+        }
+
+        let stored = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        assert_eq!(
+            stored.connection_stats.peer_initiated_disconnects(),
+            PEER_DISCONNECT_QUARANTINE_THRESHOLD
+        );
+        assert!(stored.is_quarantined());
+        assert!(peer_manager
+            .closest_eligible_peers(&target, 1, &[], None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(peer_manager.connectable_at(&peer.node_id).await.unwrap().is_some());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn closest_peers_where() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let target = create_test_peer(false, Default::default()).node_id;
+        let mut peers = (0..10)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| target.distance(&a.node_id).cmp(&target.distance(&b.node_id)));
+        for (i, peer) in peers.iter_mut().enumerate() {
+            if i % 2 == 0 {
+                peer.addresses = "/ip4/10.0.0.1/tcp/8000".parse::<Multiaddr>().unwrap().into();
+            }
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+
+        let private_address = "/ip4/10.0.0.1/tcp/8000".parse::<Multiaddr>().unwrap();
+        let closest = peer_manager
+            .closest_peers_where(&target, 3, |peer| {
+                peer.addresses.address_iter().all(|addr| *addr != private_address)
+            })
+            .await
+            .unwrap();
+        assert_eq!(closest.iter().map(|p| &p.node_id).collect::<Vec<_>>(), vec![
+            &peers[1].node_id,
+            &peers[3].node_id,
+            &peers[5].node_id
+        ]);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn peers_changed_since() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peers = (0..5)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+        assert_eq!(peer_manager.peers_changed_since(0).await.unwrap().len(), 5);
+
+        let checkpoint = peer_manager.revision().await.unwrap();
+        assert!(peer_manager.peers_changed_since(checkpoint).await.unwrap().is_empty());
+
+        peer_manager
+            .ban_for(&peers[3].public_key, Duration::from_secs(100))
+            .await
+            .unwrap();
+        peer_manager.set_last_connect_success(&peers[1].node_id).await.unwrap();
+        let new_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(new_peer.clone()).await.unwrap();
+
+        let changed = peer_manager.peers_changed_since(checkpoint).await.unwrap();
+        assert_eq!(changed.iter().map(|p| &p.node_id).collect::<Vec<_>>(), vec![
+            &peers[3].node_id,
+            &peers[1].node_id,
+            &new_peer.node_id
+        ]);
+        assert!(changed.iter().all(|p| p.revision() > checkpoint));
+        assert_eq!(changed[2].revision(), peer_manager.revision().await.unwrap());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn perform_queries() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        for _ in 0..3 {
+            peer_manager
+                .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+        for _ in 0..4 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+        for _ in 0..5 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+                .await
+                .unwrap();
+        }
+
+        let results = peer_manager
+            .perform_queries(vec![
+                PeerQuery::new().select_where(|peer| peer.is_banned()),
+                PeerQuery::new()
+                    .select_where(|peer| !peer.is_banned() && peer.features == PeerFeatures::COMMUNICATION_NODE),
+                PeerQuery::new().select_where(|peer| peer.features == PeerFeatures::COMMUNICATION_CLIENT),
+                PeerQuery::new().limit(2),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].len(), 3);
+        assert!(results[0].iter().all(|p| p.is_banned()));
+        assert_eq!(results[1].len(), 4);
+        assert!(results[1]
+            .iter()
+            .all(|p| !p.is_banned() && p.features == PeerFeatures::COMMUNICATION_NODE));
+        assert_eq!(results[2].len(), 5);
+        assert!(results[2]
+            .iter()
+            .all(|p| p.features == PeerFeatures::COMMUNICATION_CLIENT));
+        assert_eq!(results[3].len(), 2);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn new_in_memory() {
+        let in_memory = PeerManager::new_in_memory().unwrap();
+        let db_backed = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peers = (0..10)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer_manager in &[&in_memory, &db_backed] {
+            for peer in &peers {
+                peer_manager.add_peer(peer.clone()).await.unwrap();
+            }
+            peer_manager
+                .ban_for(&peers[0].public_key, Duration::from_secs(100))
+                .await
+                .unwrap();
+            peer_manager.delete_peer(&peers[1].node_id).await.unwrap();
+        }
+
+        assert_eq!(
+            in_memory.find_by_node_id(&peers[0].node_id).await.unwrap().is_banned(),
+            db_backed.find_by_node_id(&peers[0].node_id).await.unwrap().is_banned()
+        );
+        assert!(!in_memory.exists_node_id(&peers[1].node_id).await);
+        assert_eq!(
+            in_memory.all().await.unwrap().len(),
+            db_backed.all().await.unwrap().len()
+        );
+        let target = &peers[5].node_id;
+        let closest_in_memory = in_memory.closest_peers(target, 3, &[], None).await.unwrap();
+        let closest_db_backed = db_backed.closest_peers(target, 3, &[], None).await.unwrap();
+        assert_eq!(
+            closest_in_memory.iter().map(|p| &p.node_id).collect::<Vec<_>>(),
+            closest_db_backed.iter().map(|p| &p.node_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio_macros::test_basic]
+    async fn random_peers_distinct_subnets() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let addresses = [
+            "/ip4/10.0.0.1/tcp/8000",
+            "/ip4/10.0.0.2/tcp/8000",
+            "/ip4/10.0.0.3/tcp/8000",
+            "/ip4/10.0.0.4/tcp/8000",
+            "/ip4/10.1.0.1/tcp/8000",
+            "/ip4/10.2.0.1/tcp/8000",
+            "/ip4/10.3.0.1/tcp/8000",
+        ];
+        for address in &addresses {
+            let mut peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+            peer.addresses = address.parse::<Multiaddr>().unwrap().into();
+            peer_manager.add_peer(peer).await.unwrap();
+        }
+        let subnet_of = |peer: &Peer| {
+            peer.addresses[0]
+                .address
+                .to_string()
+                .split('.')
+                .take(2)
+                .collect::<String>()
+        };
+
+        for _ in 0..10 {
+            let peers = peer_manager.random_peers_distinct_subnets(4, 24, vec![]).await.unwrap();
+            assert_eq!(peers.len(), 4);
+            let subnets = peers.iter().map(subnet_of).collect::<HashSet<_>>();
+            assert_eq!(subnets.len(), 4);
+        }
+
+        // Falls back to peers that share a subnet when there are not enough distinct subnets
+        let peers = peer_manager.random_peers_distinct_subnets(6, 24, vec![]).await.unwrap();
+        assert_eq!(peers.len(), 6);
+        assert_eq!(peers.iter().map(subnet_of).collect::<HashSet<_>>().len(), 4);
+
+        // All peers share a /8
+        let peers = peer_manager.random_peers_distinct_subnets(1, 8, vec![]).await.unwrap();
+        assert_eq!(peers.len(), 1);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn reconcile() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let updated = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let unchanged = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let missing = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let missing_banned = create_test_peer(true, PeerFeatures::COMMUNICATION_NODE);
+        let new = create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT);
+        for peer in &[&updated, &unchanged, &missing, &missing_banned] {
+            peer_manager.add_peer((*peer).clone()).await.unwrap();
+        }
+        peer_manager
+            .ban_for(&updated.public_key, Duration::from_secs(1000))
+            .await
+            .unwrap();
+        peer_manager
+            .set_alias(&updated.node_id, Some("updated".to_string()))
+            .await
+            .unwrap();
+
+        let mut updated_export = PeerExport::from(&updated);
+        updated_export.addresses = vec!["/ip4/5.6.7.8/tcp/8000".parse().unwrap()];
+        let mut invalid_export = PeerExport::from(&new);
+        invalid_export.node_id = unchanged.node_id.clone();
+        let snapshot = vec![
+            updated_export,
+            PeerExport::from(&unchanged),
+            PeerExport::from(&new),
+            invalid_export,
+        ];
+
+        let report = peer_manager.reconcile(snapshot.clone(), false).await.unwrap();
+        assert_eq!(report, ReconcileReport {
+            added: 1,
+            updated: 1,
+            removed: 0,
+        });
+        assert!(peer_manager.exists(&new.public_key).await);
+        assert!(peer_manager.exists(&missing.public_key).await);
+        let peer = peer_manager.find_by_node_id(&updated.node_id).await.unwrap();
+        assert_eq!(peer.addresses.len(), 1);
+        assert_eq!(
+            peer.addresses[0].address,
+            "/ip4/5.6.7.8/tcp/8000".parse::<Multiaddr>().unwrap()
+        );
+        assert!(peer.is_banned());
+        assert_eq!(peer.alias.as_ref().unwrap(), "updated");
+
+        let report = peer_manager.reconcile(snapshot, true).await.unwrap();
+        assert_eq!(report, ReconcileReport {
+            added: 0,
+            updated: 0,
+            removed: 1,
+        });
+        assert!(!peer_manager.exists(&missing.public_key).await);
+        assert!(peer_manager.exists(&missing_banned.public_key).await);
+        assert!(peer_manager.exists(&unchanged.public_key).await);
+        assert_eq!(peer_manager.all().await.unwrap().len(), 4);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn deterministic_peers() {
+        let peers = (0..20)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        let peer_manager1 = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer_manager2 = PeerManager::new(HashmapDatabase::new()).unwrap();
+        for peer in &peers {
+            peer_manager1.add_peer(peer.clone()).await.unwrap();
+        }
+        // Add peers in a different order
+        for peer in peers.iter().rev() {
+            peer_manager2.add_peer(peer.clone()).await.unwrap();
+        }
+        peer_manager1
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+        peer_manager1
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+
+        let node_ids = |peers: Vec<Peer>| peers.into_iter().map(|p| p.node_id).collect::<Vec<_>>();
+        let selected = node_ids(
+            peer_manager1
+                .deterministic_peers(b"epoch-1", 5, PeerFeatures::COMMUNICATION_NODE)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(selected.len(), 5);
+        assert!(selected.iter().all(|n| peers.iter().any(|p| p.node_id == *n)));
+        let selected2 = node_ids(
+            peer_manager2
+                .deterministic_peers(b"epoch-1", 5, PeerFeatures::COMMUNICATION_NODE)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(selected, selected2);
+
+        let other_epoch = node_ids(
+            peer_manager1
+                .deterministic_peers(b"epoch-2", 20, PeerFeatures::COMMUNICATION_NODE)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(other_epoch.len(), 20);
+        assert_ne!(&other_epoch[..5], selected.as_slice());
+    }
+
+    #[cfg(feature = "peer-store-lock-metrics")]
+    #[tokio_macros::test_basic]
+    async fn lock_metrics_record_wait_times() {
+        let peer_manager = Arc::new(PeerManager::new(HashmapDatabase::new()).unwrap());
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        let metrics = peer_manager.lock_metrics();
+        assert_eq!(metrics.write.num_acquisitions(), 1);
+
+        let write_lock = peer_manager.peer_storage.write().await;
+        let reader = tokio::spawn({
+            let peer_manager = peer_manager.clone();
+            async move { peer_manager.all().await.unwrap().len() }
+        });
+        time::delay_for(Duration::from_millis(20)).await;
+        drop(write_lock);
+        assert_eq!(reader.await.unwrap(), 1);
+
+        let metrics = peer_manager.lock_metrics();
+        assert_eq!(metrics.read.num_acquisitions(), 1);
+        assert!(metrics.read.max_wait >= Duration::from_millis(10));
+        assert_eq!(metrics.read.total_wait, metrics.read.max_wait);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn find_or_create() {
+        let peer_manager = Arc::new(PeerManager::new(HashmapDatabase::new()).unwrap());
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let num_created = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..10).map(|_| {
+            let peer_manager = peer_manager.clone();
+            let peer = peer.clone();
+            let num_created = num_created.clone();
+            tokio::spawn(async move {
+                let public_key = peer.public_key.clone();
+                peer_manager
+                    .find_or_create(&public_key, || {
+                        num_created.fetch_add(1, Ordering::SeqCst);
+                        peer
+                    })
+                    .await
+                    .unwrap()
+            })
+        });
+        for found in future::join_all(tasks).await {
+            assert_eq!(found.unwrap().node_id, peer.node_id);
+        }
+        assert_eq!(num_created.load(Ordering::SeqCst), 1);
+        assert_eq!(peer_manager.all().await.unwrap().len(), 1);
+
+        let other = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let err = peer_manager
+            .find_or_create(&other.public_key, || {
+                create_test_peer(false, PeerFeatures::COMMUNICATION_NODE)
+            })
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::PublicKeyMismatch = err);
+        assert!(!peer_manager.exists(&other.public_key).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn feature_breakdown() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        assert!(peer_manager.feature_breakdown().await.unwrap().is_empty());
+
+        let mut offline_node = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_node.set_offline(true);
+        let mut offline_banned_client = create_test_peer(true, PeerFeatures::COMMUNICATION_CLIENT);
+        offline_banned_client.set_offline(true);
+        let peers = vec![
+            create_test_peer(false, PeerFeatures::COMMUNICATION_NODE),
+            create_test_peer(true, PeerFeatures::COMMUNICATION_NODE),
+            offline_node,
+            create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT),
+            offline_banned_client,
+        ];
+        for peer in peers {
+            peer_manager.add_peer(peer).await.unwrap();
+        }
+
+        let breakdown = peer_manager.feature_breakdown().await.unwrap();
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[&PeerFeatures::COMMUNICATION_NODE], FeatureCounts {
+            total: 3,
+            online: 2,
+            offline: 1,
+            banned: 1,
+        });
+        assert_eq!(breakdown[&PeerFeatures::COMMUNICATION_CLIENT], FeatureCounts {
+            total: 2,
+            online: 1,
+            offline: 1,
+            banned: 1,
+        });
+    }
+
+    #[tokio_macros::test_basic]
+    async fn closest_peers_outcome() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let node_id = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE).node_id;
+
+        let outcome = peer_manager
+            .closest_peers_outcome(&node_id, 3, &[], None)
+            .await
+            .unwrap();
+        assert_eq!(outcome, SelectionOutcome::NoPeers);
+        assert!(outcome.into_peers().is_empty());
+
+        let banned = create_test_peer(true, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(banned).await.unwrap();
+        let outcome = peer_manager
+            .closest_peers_outcome(&node_id, 3, &[], None)
+            .await
+            .unwrap();
+        assert_eq!(outcome, SelectionOutcome::NoEligiblePeers);
+
+        for _ in 0..2 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+        let outcome = peer_manager
+            .closest_peers_outcome(&node_id, 3, &[], None)
+            .await
+            .unwrap();
+        unpack_enum!(SelectionOutcome::Insufficient(peers) = outcome);
+        assert_eq!(peers.len(), 2);
+
+        // Peers exist, but none have the requested features
+        let outcome = peer_manager
+            .closest_peers_outcome(&node_id, 3, &[], Some(PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+        assert_eq!(outcome, SelectionOutcome::NoEligiblePeers);
+
+        let outcome = peer_manager
+            .closest_peers_outcome(&node_id, 2, &[], None)
+            .await
+            .unwrap();
+        assert!(outcome.is_complete());
+        assert_eq!(outcome.into_peers().len(), 2);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn admission_policy() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        peer_manager
+            .set_admission_policy(Box::new(|peer| {
+                if peer.features == PeerFeatures::COMMUNICATION_CLIENT {
+                    AdmissionDecision::Reject
+                } else if peer.supported_protocols().is_empty() {
+                    AdmissionDecision::AcceptQuarantined
+                } else {
+                    AdmissionDecision::Accept
+                }
+            }))
+            .unwrap();
+
+        let client = create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT);
+        let err = peer_manager.add_peer(client.clone()).await.unwrap_err();
+        unpack_enum!(PeerManagerError::AdmissionRejected = err);
+        assert!(!peer_manager.exists(&client.public_key).await);
+
+        let mut accepted = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        accepted.supported_protocols = vec![ProtocolId::from_static(b"/tari/test/1.0")];
+        peer_manager.add_peer(accepted.clone()).await.unwrap();
+        let peer = peer_manager.find_by_node_id(&accepted.node_id).await.unwrap();
+        assert!(!peer.is_quarantined());
+        assert!(peer.is_connectable(PEER_OFFLINE_EXPIRY));
+
+        let quarantined = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(quarantined.clone()).await.unwrap();
+        let peer = peer_manager.find_by_node_id(&quarantined.node_id).await.unwrap();
+        assert!(peer.is_quarantined());
+        assert!(!peer.is_connectable(PEER_OFFLINE_EXPIRY));
+        assert!(peer.connectable_in(PEER_OFFLINE_EXPIRY).unwrap() > Duration::from_secs(59 * 60));
+
+        // The policy is also applied to peers created by find_or_create and reconcile
+        let err = peer_manager
+            .find_or_create(&client.public_key, || client.clone())
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::AdmissionRejected = err);
+        let report = peer_manager
+            .reconcile(vec![PeerExport::from(&client)], false)
+            .await
+            .unwrap();
+        assert_eq!(report.added, 0);
+        assert!(!peer_manager.exists(&client.public_key).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn export_import_peers_binary() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peers = (0..5)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+        let bytes = peer_manager.export_peers_binary().await.unwrap();
+
+        let other_peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        assert_eq!(other_peer_manager.import_peers_binary(&bytes).await.unwrap(), 5);
+        for peer in &peers {
+            let imported = other_peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+            assert_eq!(imported.public_key, peer.public_key);
+            assert_eq!(imported.features, peer.features);
+        }
+
+        let err = other_peer_manager
+            .import_peers_binary(&bytes[..bytes.len() - 1])
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::SerializationError(_s) = err);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn extend_ban() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+
+        // An unbanned peer is banned from now
+        peer_manager
+            .extend_ban(&peer.public_key, Duration::from_secs(100))
+            .await
+            .unwrap();
+        let banned = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        let first_expiry = *banned.banned_until().unwrap();
+        let remaining = banned.ban_remaining().unwrap();
+        assert!(remaining > Duration::from_secs(90) && remaining <= Duration::from_secs(100));
+
+        peer_manager
+            .extend_ban(&peer.public_key, Duration::from_secs(1000))
+            .await
+            .unwrap();
+        let banned = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        assert!(*banned.banned_until().unwrap() > first_expiry);
+        let remaining = banned.ban_remaining().unwrap();
+        assert!(remaining > Duration::from_secs(1090) && remaining <= Duration::from_secs(1100));
+
+        // An extension that overflows is clamped, and the published duration matches the stored ban
+        let mut changes = peer_manager.subscribe_changes();
+        peer_manager
+            .extend_ban(&peer.public_key, Duration::from_secs(u64::max_value()))
+            .await
+            .unwrap();
+        let change = changes.try_recv().unwrap();
+        unpack_enum!(PeerStoreChangeKind::Banned(published) = &change.kind);
+        let published = *published;
+        let banned = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        let remaining = banned.ban_remaining().unwrap();
+        assert!(published >= remaining && published - remaining < Duration::from_secs(1));
+
+        let err = peer_manager
+            .extend_ban(
+                &create_test_peer(false, PeerFeatures::COMMUNICATION_NODE).public_key,
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.is_peer_not_found());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn add_and_remove_features() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::MESSAGE_PROPAGATION);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+        let mut changes = peer_manager.subscribe_changes();
+
+        let features = peer_manager
+            .add_features(&peer.node_id, PeerFeatures::DHT_STORE_FORWARD)
+            .await
+            .unwrap();
+        assert_eq!(features, PeerFeatures::COMMUNICATION_NODE);
+        assert_eq!(
+            peer_manager.get_peer_features(&peer.node_id).await.unwrap(),
+            PeerFeatures::COMMUNICATION_NODE
+        );
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.node_id, peer.node_id);
+        unpack_enum!(PeerStoreChangeKind::FeaturesChanged(changed) = &change.kind);
+        assert_eq!(*changed, PeerFeatures::COMMUNICATION_NODE);
+
+        // Adding a feature that is already set does not change the peer
+        let features = peer_manager
+            .add_features(&peer.node_id, PeerFeatures::MESSAGE_PROPAGATION)
+            .await
+            .unwrap();
+        assert_eq!(features, PeerFeatures::COMMUNICATION_NODE);
+
+        let features = peer_manager
+            .remove_features(&peer.node_id, PeerFeatures::MESSAGE_PROPAGATION)
+            .await
+            .unwrap();
+        assert_eq!(features, PeerFeatures::DHT_STORE_FORWARD);
+        let change = changes.try_recv().unwrap();
+        unpack_enum!(PeerStoreChangeKind::FeaturesChanged(changed) = &change.kind);
+        assert_eq!(*changed, PeerFeatures::DHT_STORE_FORWARD);
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn has_min_connectable() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        assert!(peer_manager.has_min_connectable(None, 0).await.unwrap());
+        assert!(!peer_manager.has_min_connectable(None, 1).await.unwrap());
+
+        for _ in 0..3 {
+            peer_manager
+                .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+                .await
+                .unwrap();
+        }
+        // Banned peers and peers with other features are not counted
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+
+        let features = Some(PeerFeatures::COMMUNICATION_NODE);
+        assert!(peer_manager.has_min_connectable(features, 3).await.unwrap());
+        assert!(!peer_manager.has_min_connectable(features, 4).await.unwrap());
+        assert!(peer_manager.has_min_connectable(None, 4).await.unwrap());
+        assert!(!peer_manager.has_min_connectable(None, 5).await.unwrap());
+    }
+
+    #[tokio_macros::test_basic]
+    async fn build_route() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peers = (0..20)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer in &peers {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+        peer_manager
+            .add_peer(create_test_peer(true, PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        let target = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE).node_id;
+        let closest = peers.iter().min_by_key(|p| target.distance(&p.node_id)).unwrap();
+
+        let route = peer_manager.build_route(&target, 4, None).await.unwrap();
+        assert_eq!(route.len(), 4);
+        assert!(route
+            .windows(2)
+            .all(|hops| target.distance(&hops[1].node_id) < target.distance(&hops[0].node_id)));
+        assert_eq!(route.last().unwrap().node_id, closest.node_id);
+        assert!(route.iter().all(|p| !p.is_banned()));
+
+        // Only peers closer to the target than the local node are used
+        let (_sk, local_public_key) = RistrettoPublicKey::random_keypair(&mut OsRng);
+        let local_node_id = NodeId::from_key(&local_public_key).unwrap();
+        peer_manager.set_local_identity(local_public_key).unwrap();
+        let num_closer = peers
+            .iter()
+            .filter(|p| target.distance(&p.node_id) < target.distance(&local_node_id))
+            .count();
+        let route = peer_manager.build_route(&target, 20, None).await.unwrap();
+        assert_eq!(route.len(), num_closer);
+        assert!(route
+            .iter()
+            .all(|p| target.distance(&p.node_id) < target.distance(&local_node_id)));
+    }
+
+    #[tokio_macros::test_basic]
+    async fn export_where() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let base_nodes = (0..3)
+            .map(|_| create_test_peer(false, PeerFeatures::COMMUNICATION_NODE))
+            .collect::<Vec<_>>();
+        for peer in &base_nodes {
+            peer_manager.add_peer(peer.clone()).await.unwrap();
+        }
+        let mut offline_base_node = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_base_node.set_offline(true);
+        peer_manager.add_peer(offline_base_node).await.unwrap();
+        peer_manager
+            .add_peer(create_test_peer(false, PeerFeatures::COMMUNICATION_CLIENT))
+            .await
+            .unwrap();
+
+        let exports = peer_manager
+            .export_where(|peer| peer.features == PeerFeatures::COMMUNICATION_NODE && !peer.is_offline())
+            .await
+            .unwrap();
+        assert_eq!(exports.len(), 3);
+        for peer in &base_nodes {
+            assert!(exports.contains(&PeerExport::from(peer)));
+        }
+    }
+
+    #[tokio_macros::test_basic]
+    async fn ban_escalating() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer.clone()).await.unwrap();
+
+        let base = Duration::from_secs(60);
+        let max = Duration::from_secs(600);
+        let mut durations = Vec::new();
+        for _ in 0..6 {
+            let (node_id, duration) = peer_manager.ban_escalating(&peer.public_key, base, max).await.unwrap();
+            assert_eq!(node_id, peer.node_id);
+            durations.push(duration.as_secs());
+        }
+        assert_eq!(durations, vec![60, 120, 240, 480, 600, 600]);
+
+        let banned = peer_manager.find_by_node_id(&peer.node_id).await.unwrap();
+        assert_eq!(banned.ban_count(), 6);
+        assert!(banned.ban_remaining().unwrap() > Duration::from_secs(590));
+    }
+
+    #[tokio_macros::test_basic]
+    async fn peers_compatible_with() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let create_peer = |features, protocols: &[&'static [u8]]| {
+            let mut peer = create_test_peer(false, features);
+            peer.supported_protocols = protocols.iter().map(|p| ProtocolId::from_static(*p)).collect();
+            peer
+        };
+        let reference = create_peer(PeerFeatures::COMMUNICATION_NODE, &[b"/tari/a/1.0", b"/tari/b/1.0"]);
+        let overlapping = create_peer(PeerFeatures::COMMUNICATION_NODE, &[b"/tari/b/1.0", b"/tari/c/1.0"]);
+        let overlapping_client = create_peer(PeerFeatures::COMMUNICATION_CLIENT, &[b"/tari/a/1.0"]);
+        let disjoint = create_peer(PeerFeatures::COMMUNICATION_NODE, &[b"/tari/c/1.0"]);
+        let no_protocols = create_peer(PeerFeatures::COMMUNICATION_NODE, &[]);
+        let mut banned = create_peer(PeerFeatures::COMMUNICATION_NODE, &[b"/tari/a/1.0"]);
+        banned.ban_for(Duration::from_secs(1000));
+        for peer in &[
+            &reference,
+            &overlapping,
+            &overlapping_client,
+            &disjoint,
+            &no_protocols,
+            &banned,
+        ] {
+            peer_manager.add_peer((*peer).clone()).await.unwrap();
+        }
+
+        let mut compatible = peer_manager
+            .peers_compatible_with(&reference.node_id, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.node_id)
+            .collect::<Vec<_>>();
+        compatible.sort();
+        let mut expected = vec![overlapping.node_id.clone(), overlapping_client.node_id.clone()];
+        expected.sort();
+        assert_eq!(compatible, expected);
+
+        let compatible = peer_manager
+            .peers_compatible_with(&reference.node_id, Some(PeerFeatures::COMMUNICATION_NODE))
+            .await
+            .unwrap();
+        assert_eq!(compatible.len(), 1);
+        assert_eq!(compatible[0].node_id, overlapping.node_id);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn touch_many() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let mut offline_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        offline_peer.set_offline(true);
+        let peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let untouched = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        for p in &[&offline_peer, &peer, &untouched] {
+            peer_manager.add_peer((*p).clone()).await.unwrap();
+        }
+        let unknown = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+
+        let num_updated = peer_manager
+            .touch_many(&[
+                offline_peer.node_id.clone(),
+                unknown.node_id.clone(),
+                peer.node_id.clone(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(num_updated, 2);
+
+        for node_id in &[&offline_peer.node_id, &peer.node_id] {
+            let touched = peer_manager.find_by_node_id(node_id).await.unwrap();
+            assert!(!touched.is_offline());
+            assert!(touched.last_seen().is_some());
+        }
+        let untouched = peer_manager.find_by_node_id(&untouched.node_id).await.unwrap();
+        assert!(untouched.last_seen().is_none());
+        assert!(!peer_manager.exists_node_id(&unknown.node_id).await);
+    }
+
+    #[tokio_macros::test_basic]
+    async fn distance_between_peers() {
+        let peer_manager = PeerManager::new(HashmapDatabase::new()).unwrap();
+        let a = NodeId::from_bytes(&[0x0f; 13]).unwrap();
+        let b = NodeId::from_bytes(&[0xf0; 13]).unwrap();
+        let dist = peer_manager.distance_between(&a, &b);
+        assert_eq!(dist.as_bytes(), &[0xff; 13][..]);
+        assert_eq!(peer_manager.distance_between(&b, &a), dist);
+        assert_eq!(peer_manager.distance_between(&a, &a), NodeDistance::new());
+
+        let peer1 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let peer2 = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        let unknown_peer = create_test_peer(false, PeerFeatures::COMMUNICATION_NODE);
+        peer_manager.add_peer(peer1.clone()).await.unwrap();
+        peer_manager.add_peer(peer2.clone()).await.unwrap();
+
+        let dist = peer_manager
+            .peer_distance(&peer1.node_id, &peer2.node_id)
+            .await
+            .unwrap();
+        assert_eq!(dist, peer1.node_id.distance(&peer2.node_id));
+        let err = peer_manager
+            .peer_distance(&peer1.node_id, &unknown_peer.node_id)
+            .await
+            .unwrap_err();
+        unpack_enum!(PeerManagerError::PeerNotFoundError = err);
+    }
 }
@@ -0,0 +1,210 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "peer-store-lock-metrics")]
+use std::{sync::atomic::AtomicU64, time::Duration};
+
+/// A snapshot of the operation counters for the peer store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerStoreMetrics {
+    /// Number of peers added (or replaced) in the peer store
+    pub adds: usize,
+    /// Number of peer updates, including ban, offline and connection stat changes
+    pub updates: usize,
+    /// Number of peers deleted from the peer store
+    pub deletes: usize,
+    /// Number of lookups by node id or public key
+    pub lookups: usize,
+    /// Number of lookups that were resolved by the peer index
+    pub cache_hits: usize,
+    /// Number of lookups for peers that are not in the peer index
+    pub cache_misses: usize,
+}
+
+/// Lock-free counters for peer store operations. These are updated outside of the peer storage lock so that they do
+/// not add any contention.
+#[derive(Debug, Default)]
+pub(super) struct PeerStoreCounters {
+    adds: AtomicUsize,
+    updates: AtomicUsize,
+    deletes: AtomicUsize,
+    lookups: AtomicUsize,
+    cache_hits: AtomicUsize,
+    cache_misses: AtomicUsize,
+}
+
+impl PeerStoreCounters {
+    pub fn inc_adds(&self) {
+        self.adds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_updates(&self) {
+        self.add_updates(1);
+    }
+
+    pub fn add_updates(&self, n: usize) {
+        self.updates.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc_deletes(&self) {
+        self.deletes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a lookup. `is_hit` should be true if the peer was found.
+    pub fn record_lookup(&self, is_hit: bool) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        if is_hit {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> PeerStoreMetrics {
+        PeerStoreMetrics {
+            adds: self.adds.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            lookups: self.lookups.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The upper bounds of the lock wait time histogram buckets. Waits longer than the last bound are counted in a final,
+/// unbounded bucket.
+#[cfg(feature = "peer-store-lock-metrics")]
+pub const LOCK_WAIT_BUCKET_BOUNDS: [Duration; 6] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+#[cfg(feature = "peer-store-lock-metrics")]
+const NUM_LOCK_WAIT_BUCKETS: usize = 7;
+
+/// A snapshot of the time spent waiting to acquire the peer storage lock
+#[cfg(feature = "peer-store-lock-metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockMetrics {
+    /// Wait times to acquire the read lock
+    pub read: LockWaitHistogram,
+    /// Wait times to acquire the write lock
+    pub write: LockWaitHistogram,
+}
+
+/// A histogram of lock wait times
+#[cfg(feature = "peer-store-lock-metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockWaitHistogram {
+    /// The number of lock acquisitions in each bucket. `counts[i]` is the number of waits no longer than
+    /// `LOCK_WAIT_BUCKET_BOUNDS[i]` (and longer than the previous bound), the last entry counts the longer waits.
+    pub counts: [usize; NUM_LOCK_WAIT_BUCKETS],
+    /// The sum of all wait times
+    pub total_wait: Duration,
+    /// The longest wait time
+    pub max_wait: Duration,
+}
+
+#[cfg(feature = "peer-store-lock-metrics")]
+impl LockWaitHistogram {
+    /// The total number of lock acquisitions
+    pub fn num_acquisitions(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+/// Lock-free recorders for peer storage lock wait times
+#[cfg(feature = "peer-store-lock-metrics")]
+#[derive(Debug, Default)]
+pub(super) struct LockWaitCounters {
+    read: LockWaitRecorder,
+    write: LockWaitRecorder,
+}
+
+#[cfg(feature = "peer-store-lock-metrics")]
+impl LockWaitCounters {
+    pub fn record_read(&self, wait: Duration) {
+        self.read.record(wait);
+    }
+
+    pub fn record_write(&self, wait: Duration) {
+        self.write.record(wait);
+    }
+
+    pub fn snapshot(&self) -> LockMetrics {
+        LockMetrics {
+            read: self.read.snapshot(),
+            write: self.write.snapshot(),
+        }
+    }
+}
+
+#[cfg(feature = "peer-store-lock-metrics")]
+#[derive(Debug, Default)]
+struct LockWaitRecorder {
+    counts: [AtomicUsize; NUM_LOCK_WAIT_BUCKETS],
+    total_wait_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+}
+
+#[cfg(feature = "peer-store-lock-metrics")]
+impl LockWaitRecorder {
+    fn record(&self, wait: Duration) {
+        let bucket = LOCK_WAIT_BUCKET_BOUNDS
+            .iter()
+            .position(|bound| wait <= *bound)
+            .unwrap_or(LOCK_WAIT_BUCKET_BOUNDS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+
+        let wait_nanos = wait.as_nanos() as u64;
+        self.total_wait_nanos.fetch_add(wait_nanos, Ordering::Relaxed);
+        let mut max_wait_nanos = self.max_wait_nanos.load(Ordering::Relaxed);
+        while wait_nanos > max_wait_nanos {
+            match self.max_wait_nanos.compare_exchange_weak(
+                max_wait_nanos,
+                wait_nanos,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => max_wait_nanos = current,
+            }
+        }
+    }
+
+    fn snapshot(&self) -> LockWaitHistogram {
+        let mut counts = [0; NUM_LOCK_WAIT_BUCKETS];
+        for (count, counter) in counts.iter_mut().zip(self.counts.iter()) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+        LockWaitHistogram {
+            counts,
+            total_wait: Duration::from_nanos(self.total_wait_nanos.load(Ordering::Relaxed)),
+            max_wait: Duration::from_nanos(self.max_wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
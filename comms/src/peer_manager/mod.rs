@@ -61,7 +61,23 @@
 //! let returned_peer = peer_manager.find_by_node_id(&node_id).unwrap();
 //! ```
 
+mod admission;
+pub use admission::{AdmissionDecision, AdmissionPolicy};
+
+mod ban_record;
+pub use ban_record::BanRecord;
+
+mod binary_format;
+pub use binary_format::{PeerBinaryFormatError, PEER_BINARY_FORMAT_VERSION};
+
+mod change_log;
+pub use change_log::{PeerStoreChange, PeerStoreChangeKind};
+
 mod connection_stats;
+pub use connection_stats::ConnectionFailureReason;
+
+mod distance_metric;
+pub use distance_metric::{DistanceMetric, XorDistance};
 
 mod error;
 pub use error::PeerManagerError;
@@ -75,6 +91,11 @@ pub use node_identity::{NodeIdentity, NodeIdentityError};
 mod peer;
 pub use peer::{Peer, PeerFlags};
 
+mod peer_database;
+
+mod peer_export;
+pub use peer_export::{PeerExport, ReconcileReport};
+
 mod peer_features;
 pub use peer_features::PeerFeatures;
 
@@ -84,8 +105,16 @@ pub use peer_id::PeerId;
 mod manager;
 pub use manager::PeerManager;
 
+mod metrics;
+pub use metrics::PeerStoreMetrics;
+#[cfg(feature = "peer-store-lock-metrics")]
+pub use metrics::{LockMetrics, LockWaitHistogram, LOCK_WAIT_BUCKET_BOUNDS};
+
 mod peer_query;
 pub use peer_query::{PeerQuery, PeerQuerySortBy};
 
 mod peer_storage;
-pub use peer_storage::PeerStorage;
+pub use peer_storage::{CompactionReport, FeatureCounts, PeerStorage};
+
+mod selection_outcome;
+pub use selection_outcome::SelectionOutcome;
@@ -88,6 +88,19 @@ impl NodeDistance {
 
         set_bit_count
     }
+
+    /// Returns the number of leading zero bits of the distance. A distance with more leading zeros is always smaller
+    /// than a distance with fewer leading zeros.
+    pub fn leading_zeros(&self) -> usize {
+        let mut count = 0;
+        for b in &self.0 {
+            count += b.leading_zeros() as usize;
+            if *b != 0 {
+                break;
+            }
+        }
+        count
+    }
 }
 
 impl PartialEq for NodeDistance {
@@ -467,4 +480,15 @@ mod test {
         let hamming_dist = NodeDistance::from_node_ids(&node_id1, &node_id2).hamming_distance();
         assert_eq!(hamming_dist, 18);
     }
+
+    #[test]
+    fn leading_zeros() {
+        assert_eq!(NodeDistance::new().leading_zeros(), NODE_ID_ARRAY_SIZE * 8);
+        assert_eq!(NodeDistance::max_distance().leading_zeros(), 0);
+
+        let mut bytes = [0; NODE_ID_ARRAY_SIZE];
+        bytes[1] = 0b00010000;
+        bytes[2] = 0b11111111;
+        assert_eq!(NodeDistance::from_bytes(&bytes).unwrap().leading_zeros(), 11);
+    }
 }
@@ -27,23 +27,30 @@ use super::{
     PeerFeatures,
 };
 use crate::{
-    consts::PEER_OFFLINE_COOLDOWN_PERIOD,
+    consts::{
+        PEER_BAN_COUNT_DECAY_PERIOD,
+        PEER_DISCONNECT_QUARANTINE_PERIOD,
+        PEER_DISCONNECT_QUARANTINE_THRESHOLD,
+        PEER_OFFLINE_COOLDOWN_PERIOD,
+    },
     net_address::MultiaddressesWithStats,
     protocol::ProtocolId,
     types::CommsPublicKey,
     utils::datetime::safe_future_datetime_from_duration,
 };
 use bitflags::bitflags;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Timelike, Utc};
 use multiaddr::Multiaddr;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, time::Duration};
+use std::{cmp, fmt::Display, time::Duration};
 use tari_crypto::tari_utilities::hex::serialize_to_hex;
 
 bitflags! {
     #[derive(Default, Deserialize, Serialize)]
     pub struct PeerFlags: u8 {
         const NONE = 0x00;
+        /// The peer was configured as a seed peer
+        const SEED = 0x01;
     }
 }
 
@@ -81,6 +88,27 @@ pub struct Peer {
     pub supported_protocols: Vec<ProtocolId>,
     /// Timestamp of when the peer was added to this nodes peer list
     pub added_at: NaiveDateTime,
+    /// The peer store revision at which this peer was last written
+    #[serde(default)]
+    revision: u64,
+    /// A human-friendly label for the peer, set by the node operator. This is only used for display purposes.
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// The hours (UTC) during which the peer is expected to be online, as a `(start, end)` pair where `start` is
+    /// inclusive and `end` is exclusive. The window may wrap around midnight, e.g. `(22, 6)`. A window where `start`
+    /// equals `end` covers the whole day. Peers outside of their preferred window are de-prioritized when selecting
+    /// random peers.
+    #[serde(default)]
+    pub preferred_window: Option<(u8, u8)>,
+    /// The time until which the peer is quarantined by the peer admission policy
+    #[serde(default)]
+    quarantined_until: Option<NaiveDateTime>,
+    /// The number of escalating bans applied to the peer
+    #[serde(default)]
+    ban_count: u32,
+    /// The time at which the last escalating ban was applied
+    #[serde(default)]
+    last_banned_at: Option<NaiveDateTime>,
 }
 
 impl Peer {
@@ -106,6 +134,12 @@ impl Peer {
             connection_stats: Default::default(),
             added_at: Utc::now().naive_utc(),
             supported_protocols: supported_protocols.into_iter().cloned().collect(),
+            alias: None,
+            preferred_window: None,
+            quarantined_until: None,
+            ban_count: 0,
+            last_banned_at: None,
+            revision: 0,
         }
     }
 
@@ -139,11 +173,114 @@ impl Peer {
                 .unwrap_or(false)
     }
 
+    /// Returns true if the peer has closed its connection to this node at least
+    /// [PEER_DISCONNECT_QUARANTINE_THRESHOLD](crate::consts::PEER_DISCONNECT_QUARANTINE_THRESHOLD) times in
+    /// succession (see `PeerConnectionStats::peer_initiated_disconnects`), or if the peer has been quarantined with
+    /// `quarantine_for`.
+    pub fn is_quarantined(&self) -> bool {
+        self.is_disconnect_quarantined() || self.quarantine_remaining().is_some()
+    }
+
+    fn is_disconnect_quarantined(&self) -> bool {
+        self.connection_stats.peer_initiated_disconnects() >= PEER_DISCONNECT_QUARANTINE_THRESHOLD
+    }
+
+    /// Quarantines the peer for a specified duration
+    pub fn quarantine_for(&mut self, duration: Duration) {
+        let dt = safe_future_datetime_from_duration(duration);
+        self.quarantined_until = Some(dt.naive_utc());
+    }
+
+    fn quarantine_remaining(&self) -> Option<Duration> {
+        self.quarantined_until
+            .filter(|until| *until > Utc::now().naive_utc())
+            .map(|until| (until - Utc::now().naive_utc()).to_std().unwrap_or_default())
+    }
+
     /// Returns true if the peer is marked as offline
     pub fn is_offline(&self) -> bool {
         self.offline_at.is_some()
     }
 
+    /// Returns the amount of time since the peer was marked as offline, or None if the peer is not offline
+    pub fn offline_duration(&self) -> Option<Duration> {
+        self.offline_at
+            .map(|offline_at| (Utc::now().naive_utc() - offline_at).to_std().unwrap_or_default())
+    }
+
+    /// Returns true if the peer has been marked as offline for longer than `expiry`
+    pub fn is_offline_expired(&self, expiry: Duration) -> bool {
+        self.offline_duration().map(|d| d > expiry).unwrap_or(false)
+    }
+
+    /// Returns true if the peer should be excluded from peer selection because it is marked as offline and has not been
+    /// offline for longer than `offline_expiry`. A peer that has been offline for longer than that is worth retrying.
+    pub fn is_offline_for_selection(&self, offline_expiry: Duration) -> bool {
+        self.is_offline() && !self.is_offline_expired(offline_expiry)
+    }
+
+    /// Returns true if `now` is within the peer's preferred dial window, or the peer does not have a preferred window.
+    /// A window where `start` equals `end` is treated as the whole day.
+    pub fn is_in_preferred_window(&self, now: DateTime<Utc>) -> bool {
+        match self.preferred_window {
+            Some((start, end)) => {
+                let hour = now.hour() as u8;
+                if start <= end {
+                    start == end || (hour >= start && hour < end)
+                } else {
+                    hour >= start || hour < end
+                }
+            },
+            None => true,
+        }
+    }
+
+    /// Returns true if the peer is eligible to be dialed i.e. it has at least one address, is not banned, not marked as
+    /// offline (see `is_offline_for_selection`), not within the offline cooldown period and not quarantined.
+    pub fn is_connectable(&self, offline_expiry: Duration) -> bool {
+        !self.addresses.is_empty() &&
+            !self.is_banned() &&
+            !self.is_offline_for_selection(offline_expiry) &&
+            !self.is_recently_offline() &&
+            !self.is_quarantined()
+    }
+
+    /// Returns the amount of time until the peer's ban, offline flag, offline cooldown period and quarantine have all
+    /// expired, or None if none of them currently apply. The offline flag expires after `offline_expiry`. This does not
+    /// take the peer's addresses into account.
+    pub fn connectable_in(&self, offline_expiry: Duration) -> Option<Duration> {
+        let ban_remaining = self.ban_remaining();
+        let offline_remaining = self
+            .offline_duration()
+            .and_then(|offline_for| offline_expiry.checked_sub(offline_for));
+        let cooldown_remaining = if self.is_recently_offline() {
+            self.connection_stats
+                .time_since_last_failure()
+                .and_then(|last_failure| PEER_OFFLINE_COOLDOWN_PERIOD.checked_sub(last_failure))
+        } else {
+            None
+        };
+        let quarantine_remaining = if self.is_disconnect_quarantined() {
+            self.connection_stats
+                .time_since_last_peer_initiated_disconnect()
+                .and_then(|since| PEER_DISCONNECT_QUARANTINE_PERIOD.checked_sub(since))
+        } else {
+            None
+        };
+
+        vec![
+            ban_remaining,
+            offline_remaining,
+            cooldown_remaining,
+            quarantine_remaining,
+            self.quarantine_remaining(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|remaining| *remaining > Duration::from_secs(0))
+        .max()
+    }
+
     /// TODO: Remove once we don't have to sync wallet and base node db
     pub fn unset_id(&mut self) {
         self.id = None;
@@ -153,6 +290,15 @@ impl Peer {
         self.id = Some(id);
     }
 
+    /// Returns the peer store revision at which this peer was last written, or 0 if it has never been written
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub(super) fn set_revision(&mut self, revision: u64) {
+        self.revision = revision;
+    }
+
     #[cfg(test)]
     pub(crate) fn set_id_for_test(&mut self, id: PeerId) {
         self.id = Some(id);
@@ -203,6 +349,11 @@ impl Peer {
         self.addresses.last_seen()
     }
 
+    /// Returns true if this peer is flagged as a seed peer
+    pub fn is_seed(&self) -> bool {
+        self.flags.contains(PeerFlags::SEED)
+    }
+
     /// Returns true if this peer has the given feature, otherwise false
     pub fn has_features(&self, features: PeerFeatures) -> bool {
         self.features.contains(features)
@@ -219,15 +370,66 @@ impl Peer {
         self.banned_until = Some(dt.naive_utc());
     }
 
+    /// Extends the ban of the peer by `additional`. The extension is added to the end of the current ban, or to now if
+    /// the peer is not banned. Returns the remaining duration of the resulting ban.
+    pub fn extend_ban(&mut self, additional: Duration) -> Duration {
+        let now = Utc::now().naive_utc();
+        let from = self.banned_until().copied().unwrap_or(now);
+        let additional = chrono::Duration::from_std(additional).unwrap_or_else(|_| chrono::Duration::max_value());
+        let until = from.checked_add_signed(additional).unwrap_or_else(|| {
+            chrono::MAX_DATE
+                .and_time(NaiveTime::from_hms(0, 0, 0))
+                .expect("cannot fail")
+                .naive_utc()
+        });
+        self.banned_until = Some(until);
+        (until - now).to_std().unwrap_or_default()
+    }
+
     /// Unban the peer
     pub fn unban(&mut self) {
         self.banned_until = None;
     }
 
+    /// Returns the number of escalating bans applied to the peer. The count is reduced by one for every
+    /// [PEER_BAN_COUNT_DECAY_PERIOD](crate::consts::PEER_BAN_COUNT_DECAY_PERIOD) that has passed since the last ban
+    /// expired (or since it was applied, if the peer was unbanned).
+    pub fn ban_count(&self) -> u32 {
+        let num_decay_periods = self
+            .banned_until
+            .or(self.last_banned_at)
+            .map(|since| (Utc::now().naive_utc() - since).to_std().unwrap_or_default())
+            .map(|elapsed| elapsed.as_secs() / PEER_BAN_COUNT_DECAY_PERIOD.as_secs())
+            .unwrap_or(0);
+        self.ban_count
+            .saturating_sub(cmp::min(num_decay_periods, u64::from(u32::max_value())) as u32)
+    }
+
+    /// Bans the peer for `base * 2^ban_count`, capped at `max`, and increments the ban count. Returns the duration of
+    /// the ban.
+    pub fn ban_escalating(&mut self, base: Duration, max: Duration) -> Duration {
+        let ban_count = self.ban_count();
+        let duration = 2u32
+            .checked_pow(ban_count)
+            .and_then(|factor| base.checked_mul(factor))
+            .map(|duration| cmp::min(duration, max))
+            .unwrap_or(max);
+        self.ban_count = ban_count.saturating_add(1);
+        self.last_banned_at = Some(Utc::now().naive_utc());
+        self.ban_for(duration);
+        duration
+    }
+
     pub fn banned_until(&self) -> Option<&NaiveDateTime> {
         self.banned_until.as_ref().filter(|dt| *dt > &Utc::now().naive_utc())
     }
 
+    /// Returns the remaining duration of the ban, or None if the peer is not banned
+    pub fn ban_remaining(&self) -> Option<Duration> {
+        self.banned_until()
+            .map(|until| (*until - Utc::now().naive_utc()).to_std().unwrap_or_default())
+    }
+
     /// Marks the peer as offline
     pub fn set_offline(&mut self, is_offline: bool) {
         if is_offline {
@@ -236,6 +438,15 @@ impl Peer {
             self.offline_at = None;
         }
     }
+
+    /// Marks the peer as seen now and clears the offline flag. Liveness hints do not identify an address, so the
+    /// peer's most reliable (first) address is marked as seen.
+    pub fn touch(&mut self) {
+        if let Some(address) = self.addresses.address_iter().next().cloned() {
+            self.addresses.mark_message_received(&address);
+        }
+        self.set_offline(false);
+    }
 }
 
 /// Display Peer as `[peer_id]: <pubkey>`
@@ -258,10 +469,14 @@ impl Display for Peer {
             }
             s.join(", ")
         };
+        let node_id_str = match self.alias.as_ref() {
+            Some(alias) => format!("{} {}", alias, self.node_id.short_str()),
+            None => self.node_id.short_str(),
+        };
         f.write_str(&format!(
             "{}[{}] PK={} ({}) {} {:?} {}",
             flags_str,
-            self.node_id.short_str(),
+            node_id_str,
             self.public_key,
             self.addresses
                 .addresses
@@ -283,7 +498,14 @@ impl Display for Peer {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{net_address::MultiaddressesWithStats, peer_manager::NodeId, protocol, types::CommsPublicKey};
+    use crate::{
+        consts::PEER_OFFLINE_EXPIRY,
+        net_address::MultiaddressesWithStats,
+        peer_manager::NodeId,
+        protocol,
+        types::CommsPublicKey,
+    };
+    use chrono::TimeZone;
     use serde_json::Value;
     use tari_crypto::{
         keys::PublicKey,
@@ -303,6 +525,122 @@ mod test {
         assert_eq!(peer.is_banned(), true);
         peer.ban_for(Duration::from_millis(0));
         assert_eq!(peer.is_banned(), false);
+        assert!(peer.ban_remaining().is_none());
+        peer.ban_for(Duration::from_secs(100));
+        let remaining = peer.ban_remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(100) && remaining > Duration::from_secs(90));
+    }
+
+    #[test]
+    fn is_quarantined() {
+        let mut rng = rand::rngs::OsRng;
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = MultiaddressesWithStats::from("/ip4/123.0.0.123/tcp/8000".parse::<Multiaddr>().unwrap());
+        let mut peer: Peer = Peer::new(pk, node_id, addresses, PeerFlags::default(), PeerFeatures::empty(), &[]);
+
+        for _ in 0..PEER_DISCONNECT_QUARANTINE_THRESHOLD - 1 {
+            peer.connection_stats.record_peer_initiated_disconnect();
+        }
+        assert!(!peer.is_quarantined());
+        assert!(peer.is_connectable(PEER_OFFLINE_EXPIRY));
+
+        peer.connection_stats.record_peer_initiated_disconnect();
+        assert!(peer.is_quarantined());
+        assert!(!peer.is_connectable(PEER_OFFLINE_EXPIRY));
+    }
+
+    #[test]
+    fn is_in_preferred_window() {
+        let mut rng = rand::rngs::OsRng;
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = MultiaddressesWithStats::from("/ip4/123.0.0.123/tcp/8000".parse::<Multiaddr>().unwrap());
+        let mut peer: Peer = Peer::new(pk, node_id, addresses, PeerFlags::default(), PeerFeatures::empty(), &[]);
+        let at_hour = |hour| Utc.ymd(2020, 1, 1).and_hms(hour, 30, 0);
+        assert!(peer.is_in_preferred_window(at_hour(3)));
+
+        peer.preferred_window = Some((8, 12));
+        assert!(!peer.is_in_preferred_window(at_hour(7)));
+        assert!(peer.is_in_preferred_window(at_hour(8)));
+        assert!(peer.is_in_preferred_window(at_hour(11)));
+        assert!(!peer.is_in_preferred_window(at_hour(12)));
+
+        // The window wraps around midnight
+        peer.preferred_window = Some((22, 6));
+        assert!(peer.is_in_preferred_window(at_hour(23)));
+        assert!(peer.is_in_preferred_window(at_hour(0)));
+        assert!(!peer.is_in_preferred_window(at_hour(6)));
+        assert!(!peer.is_in_preferred_window(at_hour(21)));
+
+        // A window that starts and ends on the same hour covers the whole day
+        peer.preferred_window = Some((5, 5));
+        assert!(peer.is_in_preferred_window(at_hour(4)));
+        assert!(peer.is_in_preferred_window(at_hour(5)));
+        assert!(peer.is_in_preferred_window(at_hour(17)));
+    }
+
+    #[test]
+    fn ban_count_decays() {
+        let mut rng = rand::rngs::OsRng;
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = MultiaddressesWithStats::from("/ip4/123.0.0.123/tcp/8000".parse::<Multiaddr>().unwrap());
+        let mut peer: Peer = Peer::new(pk, node_id, addresses, PeerFlags::default(), PeerFeatures::empty(), &[]);
+        let base = Duration::from_secs(10);
+        let max = Duration::from_secs(1000);
+        assert_eq!(peer.ban_escalating(base, max), Duration::from_secs(10));
+        assert_eq!(peer.ban_escalating(base, max), Duration::from_secs(20));
+        assert_eq!(peer.ban_escalating(base, max), Duration::from_secs(40));
+        assert_eq!(peer.ban_count(), 3);
+
+        // The last ban expired two decay periods ago
+        let decay_period = chrono::Duration::from_std(PEER_BAN_COUNT_DECAY_PERIOD).unwrap();
+        peer.banned_until = Some(Utc::now().naive_utc() - decay_period * 2);
+        assert_eq!(peer.ban_count(), 1);
+        assert_eq!(peer.ban_escalating(base, max), Duration::from_secs(20));
+        assert_eq!(peer.ban_count(), 2);
+    }
+
+    #[test]
+    fn connectable_in() {
+        let mut rng = rand::rngs::OsRng;
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = MultiaddressesWithStats::from("/ip4/123.0.0.123/tcp/8000".parse::<Multiaddr>().unwrap());
+        let mut peer: Peer = Peer::new(pk, node_id, addresses, PeerFlags::default(), PeerFeatures::empty(), &[]);
+        assert!(peer.connectable_in(PEER_OFFLINE_EXPIRY).is_none());
+
+        peer.connection_stats.set_connection_failed();
+        peer.connection_stats.set_connection_failed();
+        assert!(!peer.is_connectable(PEER_OFFLINE_EXPIRY));
+        let remaining = peer.connectable_in(PEER_OFFLINE_EXPIRY).unwrap();
+        assert!(remaining <= PEER_OFFLINE_COOLDOWN_PERIOD && remaining > Duration::from_secs(50));
+
+        // The longest restriction determines when the peer is connectable
+        peer.ban_for(Duration::from_secs(100));
+        let remaining = peer.connectable_in(PEER_OFFLINE_EXPIRY).unwrap();
+        assert!(remaining <= Duration::from_secs(100) && remaining > Duration::from_secs(90));
+
+        peer.unban();
+        peer.connection_stats.set_connection_success();
+        assert!(peer.connectable_in(PEER_OFFLINE_EXPIRY).is_none());
+    }
+
+    #[test]
+    fn is_offline_for_selection() {
+        let mut rng = rand::rngs::OsRng;
+        let (_sk, pk) = RistrettoPublicKey::random_keypair(&mut rng);
+        let node_id = NodeId::from_key(&pk).unwrap();
+        let addresses = MultiaddressesWithStats::from("/ip4/123.0.0.123/tcp/8000".parse::<Multiaddr>().unwrap());
+        let mut peer: Peer = Peer::new(pk, node_id, addresses, PeerFlags::default(), PeerFeatures::empty(), &[]);
+        assert!(!peer.is_offline_for_selection(PEER_OFFLINE_EXPIRY));
+
+        peer.offline_at = Some(Utc::now().naive_utc() - chrono::Duration::hours(2));
+        assert!(peer.is_offline_for_selection(PEER_OFFLINE_EXPIRY));
+        assert!(!peer.is_offline_for_selection(Duration::from_secs(60 * 60)));
+        assert!(peer.connectable_in(PEER_OFFLINE_EXPIRY).unwrap() > Duration::from_secs(21 * 60 * 60));
+        assert!(peer.connectable_in(Duration::from_secs(60 * 60)).is_none());
     }
 
     #[test]
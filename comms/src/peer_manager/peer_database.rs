@@ -0,0 +1,79 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    peer_manager::{Peer, PeerId},
+    types::CommsDatabase,
+};
+use tari_storage::{HashmapDatabase, IterationResult, KeyValStoreError, KeyValueStore};
+
+/// The database backing the `PeerManager`. This is either the persistent `CommsDatabase` or an in-memory database
+/// that is never persisted.
+pub(crate) enum PeerDatabase {
+    Persistent(CommsDatabase),
+    InMemory(HashmapDatabase<PeerId, Peer>),
+}
+
+impl KeyValueStore<PeerId, Peer> for PeerDatabase {
+    fn insert(&self, key: PeerId, value: Peer) -> Result<(), KeyValStoreError> {
+        match self {
+            PeerDatabase::Persistent(db) => db.insert(key, value),
+            PeerDatabase::InMemory(db) => db.insert(key, value),
+        }
+    }
+
+    fn get(&self, key: &PeerId) -> Result<Option<Peer>, KeyValStoreError> {
+        match self {
+            PeerDatabase::Persistent(db) => db.get(key),
+            PeerDatabase::InMemory(db) => db.get(key),
+        }
+    }
+
+    fn size(&self) -> Result<usize, KeyValStoreError> {
+        match self {
+            PeerDatabase::Persistent(db) => db.size(),
+            PeerDatabase::InMemory(db) => db.size(),
+        }
+    }
+
+    fn for_each<F>(&self, f: F) -> Result<(), KeyValStoreError>
+    where F: FnMut(Result<(PeerId, Peer), KeyValStoreError>) -> IterationResult {
+        match self {
+            PeerDatabase::Persistent(db) => db.for_each(f),
+            PeerDatabase::InMemory(db) => db.for_each(f),
+        }
+    }
+
+    fn exists(&self, key: &PeerId) -> Result<bool, KeyValStoreError> {
+        match self {
+            PeerDatabase::Persistent(db) => db.exists(key),
+            PeerDatabase::InMemory(db) => db.exists(key),
+        }
+    }
+
+    fn delete(&self, key: &PeerId) -> Result<(), KeyValStoreError> {
+        match self {
+            PeerDatabase::Persistent(db) => db.delete(key),
+            PeerDatabase::InMemory(db) => db.delete(key),
+        }
+    }
+}
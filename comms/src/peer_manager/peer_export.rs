@@ -0,0 +1,87 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    peer_manager::{
+        node_id::{deserialize_node_id_from_hex, NodeId},
+        Peer,
+        PeerFeatures,
+    },
+    protocol::ProtocolId,
+    types::CommsPublicKey,
+};
+use multiaddr::Multiaddr;
+use serde::{Deserialize, Serialize};
+use tari_crypto::tari_utilities::hex::serialize_to_hex;
+
+/// The shareable part of a peer record. This excludes state that is local to this node, such as bans, connection
+/// statistics, offline flags and aliases, and is used to synchronise peers with another source using
+/// `PeerManager::reconcile`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PeerExport {
+    /// Public key of the peer
+    pub public_key: CommsPublicKey,
+    /// NodeId of the peer
+    #[serde(serialize_with = "serialize_to_hex")]
+    #[serde(deserialize_with = "deserialize_node_id_from_hex")]
+    pub node_id: NodeId,
+    /// Peer's addresses
+    pub addresses: Vec<Multiaddr>,
+    /// Features supported by the peer
+    pub features: PeerFeatures,
+    /// Protocols supported by the peer
+    pub supported_protocols: Vec<ProtocolId>,
+}
+
+impl From<&Peer> for PeerExport {
+    fn from(peer: &Peer) -> Self {
+        Self {
+            public_key: peer.public_key.clone(),
+            node_id: peer.node_id.clone(),
+            addresses: peer.addresses.address_iter().cloned().collect(),
+            features: peer.features,
+            supported_protocols: peer.supported_protocols.clone(),
+        }
+    }
+}
+
+impl PeerExport {
+    /// Returns true if the shareable fields of `peer` differ from this export
+    pub(super) fn differs_from(&self, peer: &Peer) -> bool {
+        self.node_id != peer.node_id ||
+            self.features != peer.features ||
+            self.supported_protocols != peer.supported_protocols ||
+            self.addresses.len() != peer.addresses.len() ||
+            peer.addresses.address_iter().any(|addr| !self.addresses.contains(addr))
+    }
+}
+
+/// The result of reconciling the peer store against a snapshot with `PeerManager::reconcile`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReconcileReport {
+    /// The number of peers in the snapshot that were not in the peer store
+    pub added: usize,
+    /// The number of existing peers that were updated to match the snapshot
+    pub updated: usize,
+    /// The number of peers that were removed because they were not in the snapshot
+    pub removed: usize,
+}
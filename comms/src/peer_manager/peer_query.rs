@@ -21,7 +21,7 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::peer_manager::{peer_id::PeerId, NodeId, Peer, PeerManagerError};
-use std::cmp::min;
+use std::cmp::{min, Ordering};
 use tari_storage::{IterationResult, KeyValueStore};
 
 type Predicate<'a, A> = Box<dyn FnMut(&A) -> bool + Send + 'a>;
@@ -33,6 +33,11 @@ pub enum PeerQuerySortBy<'a> {
     None,
     /// Sort by distance from a given node id
     DistanceFrom(&'a NodeId),
+    /// Sort by the most recent successful connection, most recent first. Peers that have never connected are sorted
+    /// last.
+    LastSeen,
+    /// Sort by the number of consecutive failed connection attempts, fewest first
+    FailedAttempts,
 }
 
 impl Default for PeerQuerySortBy<'_> {
@@ -48,6 +53,7 @@ pub struct PeerQuery<'a> {
     limit: Option<usize>,
     sort_by: PeerQuerySortBy<'a>,
     until_predicate: Option<Predicate<'a, [Peer]>>,
+    max_addresses: Option<usize>,
 }
 
 impl<'a> PeerQuery<'a> {
@@ -82,6 +88,13 @@ impl<'a> PeerQuery<'a> {
         self
     }
 
+    /// Trim the address list of each returned peer to its `max_addresses` most reliable addresses. This only affects
+    /// the returned peers, the stored peers are not modified.
+    pub fn with_max_addresses(mut self, max_addresses: usize) -> Self {
+        self.max_addresses = Some(max_addresses);
+        self
+    }
+
     /// Returns a `PeerQueryExecutor` with this `PeerQuery`
     pub(super) fn executor<DS>(self, store: &DS) -> PeerQueryExecutor<'a, '_, DS>
     where DS: KeyValueStore<PeerId, Peer> {
@@ -126,12 +139,63 @@ where DS: KeyValueStore<PeerId, Peer>
     }
 
     pub fn get_results(&mut self) -> Result<Vec<Peer>, PeerManagerError> {
+        let mut peers = self.get_unprojected_results()?;
+        if let Some(max_addresses) = self.query.max_addresses {
+            for peer in &mut peers {
+                peer.addresses.retain_most_reliable(max_addresses);
+            }
+        }
+        Ok(peers)
+    }
+
+    fn get_unprojected_results(&mut self) -> Result<Vec<Peer>, PeerManagerError> {
         match self.query.sort_by {
             PeerQuerySortBy::None => self.get_query_results(),
             PeerQuerySortBy::DistanceFrom(node_id) => self.get_distance_sorted_results(node_id),
+            PeerQuerySortBy::LastSeen => self.get_sorted_results(|a, b| {
+                // Reversed so that the most recent connection comes first. `None` is less than `Some`, so peers that
+                // have never connected are last.
+                b.connection_stats
+                    .last_connected_at
+                    .cmp(&a.connection_stats.last_connected_at)
+            }),
+            PeerQuerySortBy::FailedAttempts => self.get_sorted_results(|a, b| {
+                a.connection_stats
+                    .failed_attempts()
+                    .cmp(&b.connection_stats.failed_attempts())
+            }),
         }
     }
 
+    fn get_sorted_results<F>(&mut self, compare: F) -> Result<Vec<Peer>, PeerManagerError>
+    where F: FnMut(&Peer, &Peer) -> Ordering {
+        let mut peers = Vec::new();
+        self.store
+            .for_each_ok(|(_, peer)| {
+                if self.query.is_selected(&peer) {
+                    peers.push(peer);
+                }
+
+                IterationResult::Continue
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+
+        peers.sort_by(compare);
+        if let Some(limit) = self.query.limit {
+            peers.truncate(limit);
+        }
+
+        let mut selected_peers = Vec::with_capacity(peers.len());
+        for peer in peers {
+            selected_peers.push(peer);
+            if self.query.should_stop(&selected_peers) {
+                break;
+            }
+        }
+
+        Ok(selected_peers)
+    }
+
     pub fn get_distance_sorted_results(&mut self, node_id: &NodeId) -> Result<Vec<Peer>, PeerManagerError> {
         let mut peer_keys = Vec::new();
         let mut distances = Vec::new();
@@ -216,6 +280,7 @@ mod test {
             PeerFeatures,
         },
     };
+    use chrono::Utc;
     use multiaddr::Multiaddr;
     use rand::rngs::OsRng;
     use std::{iter::repeat_with, time::Duration};
@@ -412,4 +477,83 @@ mod test {
         })
         .unwrap();
     }
+
+    #[test]
+    fn sort_by_connection_stats_query() {
+        let db = HashmapDatabase::new();
+        let now = Utc::now().naive_utc();
+
+        let mut never_connected = create_test_peer(false);
+        never_connected.connection_stats.set_connection_failed();
+        never_connected.connection_stats.set_connection_failed();
+        db.insert(0, never_connected.clone()).unwrap();
+
+        let mut connected_long_ago = create_test_peer(false);
+        connected_long_ago.connection_stats.last_connected_at = Some(now - chrono::Duration::hours(2));
+        connected_long_ago.connection_stats.set_connection_failed();
+        db.insert(1, connected_long_ago.clone()).unwrap();
+
+        let mut connected_recently = create_test_peer(false);
+        connected_recently.connection_stats.last_connected_at = Some(now - chrono::Duration::minutes(1));
+        db.insert(2, connected_recently.clone()).unwrap();
+
+        let mut connected_a_while_ago = create_test_peer(false);
+        connected_a_while_ago.connection_stats.last_connected_at = Some(now - chrono::Duration::minutes(30));
+        db.insert(3, connected_a_while_ago.clone()).unwrap();
+
+        let peers = PeerQuery::new()
+            .sort_by(PeerQuerySortBy::LastSeen)
+            .executor(&db)
+            .get_results()
+            .unwrap();
+        assert_eq!(peers, vec![
+            connected_recently.clone(),
+            connected_a_while_ago.clone(),
+            connected_long_ago.clone(),
+            never_connected.clone(),
+        ]);
+
+        let peers = PeerQuery::new()
+            .sort_by(PeerQuerySortBy::LastSeen)
+            .limit(2)
+            .executor(&db)
+            .get_results()
+            .unwrap();
+        assert_eq!(peers, vec![connected_recently.clone(), connected_a_while_ago.clone()]);
+
+        let peers = PeerQuery::new()
+            .sort_by(PeerQuerySortBy::FailedAttempts)
+            .executor(&db)
+            .get_results()
+            .unwrap();
+        assert_eq!(peers.len(), 4);
+        assert_eq!(peers[2], connected_long_ago);
+        assert_eq!(peers[3], never_connected);
+    }
+
+    #[test]
+    fn with_max_addresses_query() {
+        let db = HashmapDatabase::new();
+        let mut peer = create_test_peer(false);
+        let reliable_address = "/ip4/5.6.7.8/tcp/8000".parse::<Multiaddr>().unwrap();
+        peer.addresses
+            .add_net_address(&"/ip4/2.3.4.5/tcp/8000".parse().unwrap());
+        peer.addresses.add_net_address(&reliable_address);
+        peer.addresses.mark_successful_connection_attempt(&reliable_address);
+        db.insert(0, peer.clone()).unwrap();
+
+        let peers = PeerQuery::new()
+            .with_max_addresses(1)
+            .executor(&db)
+            .get_results()
+            .unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].addresses.len(), 1);
+        assert_eq!(peers[0].addresses[0].address, reliable_address);
+
+        // The stored peer is untouched
+        let stored = db.get(&0).unwrap().unwrap();
+        assert_eq!(stored.addresses.len(), 3);
+        assert_eq!(stored, peer);
+    }
 }
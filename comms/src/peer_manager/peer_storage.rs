@@ -21,9 +21,10 @@
 //  USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::{
-    consts::PEER_MANAGER_MAX_FLOOD_PEERS,
+    consts::{PEER_MANAGER_MAX_FLOOD_PEERS, PEER_OFFLINE_EXPIRY},
     peer_manager::{
         connection_stats::PeerConnectionStats,
+        distance_metric::{DistanceMetric, XorDistance},
         node_id::{NodeDistance, NodeId},
         peer::{Peer, PeerFlags},
         peer_id::{generate_peer_key, PeerId},
@@ -33,11 +34,23 @@ use crate::{
     },
     protocol::ProtocolId,
     types::{CommsDatabase, CommsPublicKey},
+    utils::multiaddr::multiaddr_to_ip,
 };
+use blake2::{digest::Digest, Blake2b};
+use chrono::{DateTime, Utc};
+use cidr::{Cidr, Inet, IpCidr, IpInet};
 use log::*;
 use multiaddr::Multiaddr;
-use rand::{rngs::OsRng, Rng};
-use std::{cmp, collections::HashMap, fmt, time::Duration};
+use rand::{rngs::OsRng, seq::SliceRandom};
+use std::{
+    cmp,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
 use tari_storage::{IterationResult, KeyValueStore};
 
 const LOG_TARGET: &str = "comms::peer_manager::peer_storage";
@@ -48,6 +61,9 @@ pub struct PeerStorage<DS> {
     pub(crate) peer_db: DS,
     public_key_index: HashMap<CommsPublicKey, PeerId>,
     node_id_index: HashMap<NodeId, PeerId>,
+    distance_metric: Arc<dyn DistanceMetric>,
+    revision: u64,
+    offline_expiry: Duration,
 }
 
 impl<DS> PeerStorage<DS>
@@ -55,32 +71,82 @@ where DS: KeyValueStore<PeerId, Peer>
 {
     /// Constructs a new PeerStorage, with indexes populated from the given datastore
     pub fn new_indexed(database: DS) -> Result<PeerStorage<DS>, PeerManagerError> {
+        Self::new_indexed_with_distance_metric(database, Arc::new(XorDistance))
+    }
+
+    /// Constructs a new PeerStorage, with indexes populated from the given datastore, which uses the given
+    /// `DistanceMetric` to calculate the distance between node ids
+    pub fn new_indexed_with_distance_metric(
+        database: DS,
+        distance_metric: Arc<dyn DistanceMetric>,
+    ) -> Result<PeerStorage<DS>, PeerManagerError>
+    {
         // Restore peers and hashmap links from database
-        let mut public_key_index = HashMap::new();
-        let mut node_id_index = HashMap::new();
-        let mut total_entries = 0;
-        database
-            .for_each_ok(|(peer_key, peer)| {
-                total_entries += 1;
-                public_key_index.insert(peer.public_key, peer_key);
-                node_id_index.insert(peer.node_id, peer_key);
-                IterationResult::Continue
-            })
-            .map_err(PeerManagerError::DatabaseError)?;
+        let (public_key_index, node_id_index, revision) = Self::build_indexes(&database)?;
 
         trace!(
             target: LOG_TARGET,
             "Peer storage is initialized. {} total entries.",
-            total_entries,
+            node_id_index.len(),
         );
 
         Ok(PeerStorage {
             peer_db: database,
             public_key_index,
             node_id_index,
+            distance_metric,
+            revision,
+            offline_expiry: PEER_OFFLINE_EXPIRY,
         })
     }
 
+    /// Returns the amount of time after which a peer marked as offline is considered for selection again
+    pub fn offline_expiry(&self) -> Duration {
+        self.offline_expiry
+    }
+
+    /// Set the amount of time after which a peer marked as offline is considered for selection again. Defaults to
+    /// [PEER_OFFLINE_EXPIRY](crate::consts::PEER_OFFLINE_EXPIRY).
+    pub fn set_offline_expiry(&mut self, offline_expiry: Duration) {
+        self.offline_expiry = offline_expiry;
+    }
+
+    /// Builds the public key and node id indexes, and finds the highest peer revision in the datastore
+    fn build_indexes(
+        database: &DS,
+    ) -> Result<(HashMap<CommsPublicKey, PeerId>, HashMap<NodeId, PeerId>, u64), PeerManagerError>
+    {
+        let mut public_key_index = HashMap::new();
+        let mut node_id_index = HashMap::new();
+        let mut revision = 0;
+        database
+            .for_each_ok(|(peer_key, peer)| {
+                revision = cmp::max(revision, peer.revision());
+                public_key_index.insert(peer.public_key, peer_key);
+                node_id_index.insert(peer.node_id, peer_key);
+                IterationResult::Continue
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+        Ok((public_key_index, node_id_index, revision))
+    }
+
+    /// Rebuilds the public key and node id indexes from the peers in the datastore. Index entries that do not refer to
+    /// a stored peer are discarded and any missing index entries are restored.
+    pub fn compact(&mut self) -> Result<CompactionReport, PeerManagerError> {
+        let (public_key_index, node_id_index, _) = Self::build_indexes(&self.peer_db)?;
+        let report = CompactionReport {
+            num_peers: node_id_index.len(),
+            stale_entries_removed: count_differing_entries(&self.public_key_index, &public_key_index) +
+                count_differing_entries(&self.node_id_index, &node_id_index),
+            missing_entries_added: count_differing_entries(&public_key_index, &self.public_key_index) +
+                count_differing_entries(&node_id_index, &self.node_id_index),
+        };
+        self.public_key_index = public_key_index;
+        self.node_id_index = node_id_index;
+        debug!(target: LOG_TARGET, "Peer storage compacted: {:?}", report);
+        Ok(report)
+    }
+
     /// Adds a peer to the routing table of the PeerManager if the peer does not already exist. When a peer already
     /// exists, the stored version will be replaced with the newly provided peer.
     pub fn add_peer(&mut self, mut peer: Peer) -> Result<PeerId, PeerManagerError> {
@@ -90,9 +156,7 @@ where DS: KeyValueStore<PeerId, Peer>
                 trace!(target: LOG_TARGET, "Replacing peer that has NodeId '{}'", peer.node_id);
                 // Replace existing entry
                 peer.set_id(peer_key);
-                self.peer_db
-                    .insert(peer_key, peer)
-                    .map_err(PeerManagerError::DatabaseError)?;
+                self.put_peer(peer_key, peer)?;
                 self.remove_index_links(peer_key);
                 self.add_index_links(peer_key, public_key, node_id);
                 Ok(peer_key)
@@ -103,9 +167,7 @@ where DS: KeyValueStore<PeerId, Peer>
                 // Generate new random peer key
                 let peer_key = generate_peer_key();
                 peer.set_id(peer_key);
-                self.peer_db
-                    .insert(peer_key, peer)
-                    .map_err(PeerManagerError::DatabaseError)?;
+                self.put_peer(peer_key, peer)?;
                 self.add_index_links(peer_key, public_key, node_id);
                 Ok(peer_key)
             },
@@ -161,9 +223,7 @@ where DS: KeyValueStore<PeerId, Peer>
                 let public_key = stored_peer.public_key.clone();
                 let node_id = stored_peer.node_id.clone();
 
-                self.peer_db
-                    .insert(peer_key, stored_peer)
-                    .map_err(PeerManagerError::DatabaseError)?;
+                self.put_peer(peer_key, stored_peer)?;
 
                 if must_update_node_id {
                     trace!(target: LOG_TARGET, "Must update node id for peer '{}'", node_id);
@@ -198,6 +258,26 @@ where DS: KeyValueStore<PeerId, Peer>
         Ok(())
     }
 
+    /// The peer with the specified public_key will be removed from the PeerManager. The node id of the deleted peer is
+    /// returned.
+    pub fn delete_by_public_key(&mut self, public_key: &CommsPublicKey) -> Result<NodeId, PeerManagerError> {
+        let peer_key = *self
+            .public_key_index
+            .get(public_key)
+            .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
+        let peer = self
+            .peer_db
+            .get(&peer_key)
+            .map_err(PeerManagerError::DatabaseError)?
+            .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
+        self.peer_db
+            .delete(&peer_key)
+            .map_err(PeerManagerError::DatabaseError)?;
+
+        self.remove_index_links(peer_key);
+        Ok(peer.node_id)
+    }
+
     /// Add key pairs to the search hashmaps for a newly added or moved peer
     fn add_index_links(&mut self, peer_key: PeerId, public_key: CommsPublicKey, node_id: NodeId) {
         self.node_id_index.insert(node_id, peer_key);
@@ -214,6 +294,31 @@ where DS: KeyValueStore<PeerId, Peer>
         debug_assert_eq!(initial_size_node_id - 1, self.node_id_index.len());
     }
 
+    /// Returns the current revision of the peer store. Every write of a peer increments the revision and stamps the
+    /// peer with it.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Returns all peers that have been written since the given revision, ordered by their revision
+    pub fn peers_changed_since(&self, revision: u64) -> Result<Vec<Peer>, PeerManagerError> {
+        let mut peers = self
+            .peer_db
+            .filter(|(_, peer)| peer.revision() > revision)
+            .map_err(PeerManagerError::DatabaseError)?;
+        peers.sort_unstable_by_key(|(_, peer)| peer.revision());
+        Ok(peers.into_iter().map(|(_, peer)| peer).collect())
+    }
+
+    /// Stamps the peer with the next revision and writes it to the datastore
+    fn put_peer(&mut self, peer_key: PeerId, mut peer: Peer) -> Result<(), PeerManagerError> {
+        self.revision += 1;
+        peer.set_revision(self.revision);
+        self.peer_db
+            .insert(peer_key, peer)
+            .map_err(PeerManagerError::DatabaseError)
+    }
+
     /// Find the peer with the provided NodeID
     pub fn find_by_node_id(&self, node_id: &NodeId) -> Result<Peer, PeerManagerError> {
         let peer_key = self
@@ -240,6 +345,26 @@ where DS: KeyValueStore<PeerId, Peer>
             .expect("public_key index and peer database are out of sync"))
     }
 
+    /// Find the first peer with the given alias
+    pub fn find_by_alias(&self, alias: &str) -> Result<Peer, PeerManagerError> {
+        let mut found = None;
+        self.peer_db
+            .for_each_ok(|(_, peer)| {
+                if peer.alias.as_ref().map(|a| a == alias).unwrap_or(false) {
+                    found = Some(peer);
+                    return IterationResult::Break;
+                }
+                IterationResult::Continue
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+        found.ok_or_else(|| PeerManagerError::PeerNotFoundError)
+    }
+
+    /// Returns true if the peer store contains no peers
+    pub fn is_empty(&self) -> bool {
+        self.node_id_index.is_empty()
+    }
+
     /// Check if a peer exist using the specified public_key
     pub fn exists(&self, public_key: &CommsPublicKey) -> bool {
         self.public_key_index.contains_key(public_key)
@@ -308,84 +433,346 @@ where DS: KeyValueStore<PeerId, Peer>
         features: Option<PeerFeatures>,
     ) -> Result<Vec<Peer>, PeerManagerError>
     {
-        let mut peer_keys = Vec::new();
-        let mut dists = Vec::new();
+        self.closest_peers_where(node_id, n, |peer| {
+            features.map(|f| peer.features == f).unwrap_or(true) &&
+                !peer.is_banned() &&
+                !peer.is_offline_for_selection(self.offline_expiry) &&
+                !excluded_peers.contains(&peer.public_key)
+        })
+    }
+
+    /// Compile a list of up to n of the nearest peers that can be dialed (see `Peer::is_connectable`). Unlike
+    /// `closest_peers`, peers without addresses or within the offline cooldown period are skipped during the scan, so
+    /// the result is only under-filled if there are fewer than n connectable peers.
+    pub fn closest_eligible_peers(
+        &self,
+        node_id: &NodeId,
+        n: usize,
+        excluded_peers: &[CommsPublicKey],
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        self.closest_peers_where(node_id, n, |peer| {
+            features.map(|f| peer.features == f).unwrap_or(true) &&
+                peer.is_connectable(self.offline_expiry) &&
+                !excluded_peers.contains(&peer.public_key)
+        })
+    }
+
+    /// Compile a list of up to n of the nearest peers for which `is_match` returns true. Unlike `closest_peers`,
+    /// banned and offline peers are only excluded if `is_match` excludes them.
+    pub fn closest_peers_where<P>(
+        &self,
+        node_id: &NodeId,
+        n: usize,
+        mut is_match: P,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    where
+        P: FnMut(&Peer) -> bool,
+    {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Bucket the indexed node ids by the number of leading zero bits of their distance to node_id. Every distance
+        // in a bucket with more leading zeros is smaller than every distance in a bucket with fewer, so buckets can be
+        // scanned closest first and the scan stopped as soon as n eligible peers have been found. This avoids fetching
+        // every peer from the datastore.
+        let mut buckets = BTreeMap::new();
+        for (peer_node_id, peer_key) in &self.node_id_index {
+            let dist = self.distance_metric.distance(node_id, peer_node_id);
+            buckets
+                .entry(dist.leading_zeros())
+                .or_insert_with(Vec::new)
+                .push((dist, *peer_key));
+        }
+
+        let mut nearest_identities = Vec::with_capacity(cmp::min(n, self.node_id_index.len()));
+        for (_, mut bucket) in buckets.into_iter().rev() {
+            bucket.sort_unstable_by(|(dist_a, _), (dist_b, _)| dist_a.cmp(dist_b));
+            for (_, peer_key) in bucket {
+                let peer = self
+                    .peer_db
+                    .get(&peer_key)
+                    .map_err(PeerManagerError::DatabaseError)?
+                    .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
+                if is_match(&peer) {
+                    nearest_identities.push(peer);
+                    if nearest_identities.len() == n {
+                        return Ok(nearest_identities);
+                    }
+                }
+            }
+        }
+
+        Ok(nearest_identities)
+    }
+
+    /// Select a route of up to `hops` connectable peers toward `target`, where each peer is strictly closer to the
+    /// target than the previous one and the last peer is the closest connectable peer to the target. If `origin` is
+    /// given, only peers closer to the target than the origin are used. A shorter route is returned if there are not
+    /// enough peers that make progress toward the target.
+    pub fn build_route(
+        &self,
+        target: &NodeId,
+        hops: usize,
+        features: Option<PeerFeatures>,
+        origin: Option<&NodeId>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let origin_dist = origin.map(|origin| self.distance_metric.distance(target, origin));
+        let mut route = self.closest_peers_where(target, hops, |peer| {
+            features.map(|f| peer.features == f).unwrap_or(true) &&
+                peer.is_connectable(self.offline_expiry) &&
+                origin_dist
+                    .as_ref()
+                    .map(|origin_dist| self.distance_metric.distance(target, &peer.node_id) < *origin_dist)
+                    .unwrap_or(true)
+        })?;
+        // The closest peers are in ascending order of distance, so the route runs from the furthest to the closest
+        route.reverse();
+        Ok(route)
+    }
+
+    /// Compile a random list of up to n communication node peers that are not banned or offline, where no two peers
+    /// share an IP subnet of the given prefix length. Peers without an IP address are never considered to share a
+    /// subnet. If there are not enough peers in distinct subnets, the list is filled with peers that do share a
+    /// subnet.
+    pub fn random_peers_distinct_subnets(
+        &self,
+        n: usize,
+        prefix_len: u8,
+        exclude_peers: Vec<NodeId>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let mut candidates = self
+            .peer_db
+            .filter(|(_, peer)| {
+                !peer.is_recently_offline() &&
+                    !peer.is_offline_for_selection(self.offline_expiry) &&
+                    !peer.is_banned() &&
+                    peer.features == PeerFeatures::COMMUNICATION_NODE &&
+                    !exclude_peers.contains(&peer.node_id)
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+        candidates.shuffle(&mut OsRng);
+
+        let mut used_subnets = HashSet::new();
+        let mut selected = Vec::with_capacity(cmp::min(n, candidates.len()));
+        let mut same_subnet = Vec::new();
+        for (_, peer) in candidates {
+            if selected.len() == n {
+                break;
+            }
+            match peer_subnet(&peer, prefix_len) {
+                Some(subnet) if !used_subnets.insert(subnet) => same_subnet.push(peer),
+                _ => selected.push(peer),
+            }
+        }
+
+        let num_remaining = n.saturating_sub(selected.len());
+        selected.extend(same_subnet.into_iter().take(num_remaining));
+        Ok(selected)
+    }
+
+    /// Select n peers with the given features by ordering them by the hash of `seed` and their node id. The selection
+    /// only depends on the seed and the stored peers, so nodes with the same peers select the same subset for the same
+    /// seed. Banned and offline peers are excluded.
+    pub fn deterministic_peers(
+        &self,
+        seed: &[u8],
+        n: usize,
+        features: PeerFeatures,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let mut peers = self
+            .peer_db
+            .filter(|(_, peer)| {
+                !peer.is_banned() && !peer.is_offline_for_selection(self.offline_expiry) && peer.features == features
+            })
+            .map_err(PeerManagerError::DatabaseError)?
+            .into_iter()
+            .map(|(_, peer)| peer)
+            .collect::<Vec<_>>();
+        peers.sort_by_cached_key(|peer| {
+            let mut hasher = Blake2b::new();
+            hasher.input(seed);
+            hasher.input(&peer.node_id);
+            hasher.result().to_vec()
+        });
+        peers.truncate(n);
+        Ok(peers)
+    }
+
+    /// Fetch the n nearest neighbours of `node_id` for each of the given feature groups in a single scan of the
+    /// store. A peer belongs to a group if its features are equal to the group's features. Banned and offline peers
+    /// are excluded, as in `closest_peers`.
+    pub fn closest_peers_by_feature_groups(
+        &self,
+        node_id: &NodeId,
+        n: usize,
+        groups: &[PeerFeatures],
+    ) -> Result<HashMap<PeerFeatures, Vec<Peer>>, PeerManagerError>
+    {
+        // A bounded max-heap per group, so that the furthest of the current n nearest peers can be evicted cheaply
+        let mut heaps = groups
+            .iter()
+            .map(|group| (*group, BinaryHeap::with_capacity(n + 1)))
+            .collect::<HashMap<_, _>>();
+        let mut candidates = HashMap::new();
+        if n > 0 {
+            self.peer_db
+                .for_each_ok(|(peer_key, peer)| {
+                    if peer.is_banned() || peer.is_offline_for_selection(self.offline_expiry) {
+                        return IterationResult::Continue;
+                    }
+                    if let Some(heap) = heaps.get_mut(&peer.features) {
+                        heap.push((self.distance_metric.distance(node_id, &peer.node_id), peer_key));
+                        candidates.insert(peer_key, peer);
+                        if heap.len() > n {
+                            if let Some((_, evicted_key)) = heap.pop() {
+                                candidates.remove(&evicted_key);
+                            }
+                        }
+                    }
+                    IterationResult::Continue
+                })
+                .map_err(PeerManagerError::DatabaseError)?;
+        }
+
+        Ok(heaps
+            .into_iter()
+            .map(|(group, heap)| {
+                let peers = heap
+                    .into_sorted_vec()
+                    .into_iter()
+                    .filter_map(|(_, peer_key)| candidates.get(&peer_key).cloned())
+                    .collect();
+                (group, peers)
+            })
+            .collect())
+    }
+
+    /// Return all peers that are not banned or offline (and have the given features, if specified) ordered by their
+    /// distance to `key`, along with the distance. The first `offset` peers are skipped and at most `limit` peers are
+    /// returned.
+    pub fn peers_ranked_by_distance(
+        &self,
+        key: &NodeId,
+        offset: usize,
+        limit: usize,
+        features: Option<PeerFeatures>,
+    ) -> Result<Vec<(Peer, NodeDistance)>, PeerManagerError>
+    {
+        let mut peers = Vec::new();
         self.peer_db
-            .for_each_ok(|(peer_key, peer)| {
+            .for_each_ok(|(_, peer)| {
                 if features.map(|f| peer.features == f).unwrap_or(true) &&
                     !peer.is_banned() &&
-                    !peer.is_offline() &&
-                    !excluded_peers.contains(&peer.public_key)
+                    !peer.is_offline_for_selection(self.offline_expiry)
                 {
-                    peer_keys.push(peer_key);
-                    dists.push(node_id.distance(&peer.node_id));
+                    let dist = self.distance_metric.distance(key, &peer.node_id);
+                    peers.push((peer, dist));
                 }
                 IterationResult::Continue
             })
             .map_err(PeerManagerError::DatabaseError)?;
-        // Use all available peers up to a maximum of N
-        let max_available = cmp::min(peer_keys.len(), n);
-        if max_available == 0 {
-            return Ok(Vec::new());
-        }
 
-        // Perform partial sort of elements only up to N elements
-        let mut nearest_identities = Vec::with_capacity(max_available);
-        for i in 0..max_available {
-            for j in (i + 1)..peer_keys.len() {
-                if dists[i] > dists[j] {
-                    dists.swap(i, j);
-                    peer_keys.swap(i, j);
-                }
-            }
-            let peer = self
-                .peer_db
-                .get(&peer_keys[i])
-                .map_err(PeerManagerError::DatabaseError)?
-                .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
-            nearest_identities.push(peer);
-        }
-
-        Ok(nearest_identities)
+        peers.sort_unstable_by(|(_, dist_a), (_, dist_b)| dist_a.cmp(dist_b));
+        Ok(peers.into_iter().skip(offset).take(limit).collect())
     }
 
-    /// Compile a random list of communication node peers of size _n_ that are not banned or offline
+    /// Compile a random list of communication node peers of size _n_ that are not banned or offline. Peers that are
+    /// within their preferred dial window are selected before peers that are outside of theirs. Within each of these
+    /// groups, peers that have recently closed their connections to this node are selected last, fewest disconnects
+    /// first (see `PeerConnectionStats::peer_initiated_disconnects`).
     pub fn random_peers(&self, n: usize, exclude_peers: Vec<NodeId>) -> Result<Vec<Peer>, PeerManagerError> {
-        let mut peer_keys = self
+        self.random_peers_at(n, exclude_peers, Utc::now())
+    }
+
+    fn random_peers_at(
+        &self,
+        n: usize,
+        exclude_peers: Vec<NodeId>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Peer>, PeerManagerError>
+    {
+        let mut peers = self
             .peer_db
             .filter(|(_, peer)| {
                 !peer.is_recently_offline() &&
-                    !peer.is_offline() &&
+                    !peer.is_offline_for_selection(self.offline_expiry) &&
                     !peer.is_banned() &&
                     peer.features == PeerFeatures::COMMUNICATION_NODE &&
                     !exclude_peers.contains(&peer.node_id)
             })
-            .map(|pairs| pairs.into_iter().map(|(k, _)| k).collect::<Vec<_>>())
+            .map(|pairs| pairs.into_iter().map(|(_, peer)| peer).collect::<Vec<_>>())
             .map_err(PeerManagerError::DatabaseError)?;
 
-        // Use all available peers up to a maximum of N
-        let max_available = cmp::min(peer_keys.len(), n);
-        if max_available == 0 {
-            return Ok(Vec::new());
-        }
+        peers.shuffle(&mut OsRng);
+        // Peers outside of their preferred window are only selected if there are not enough peers within theirs, and
+        // peers that keep disconnecting are penalised before they reach the quarantine threshold. The sort is stable,
+        // so the selection remains random within each group.
+        peers.sort_by_key(|peer| {
+            (
+                !peer.is_in_preferred_window(now),
+                peer.connection_stats.peer_initiated_disconnects(),
+            )
+        });
+        peers.truncate(n);
+        Ok(peers)
+    }
 
-        // Shuffle first n elements
-        for i in 0..max_available {
-            let j = OsRng.gen_range(0, peer_keys.len());
-            peer_keys.swap(i, j);
-        }
-        // Compile list of first n shuffled elements
-        let mut random_identities = Vec::with_capacity(max_available);
-        // for i in 0..max_available {
-        for peer_key in peer_keys.iter().take(max_available) {
-            let peer = self
-                .peer_db
-                .get(peer_key)
-                .map_err(PeerManagerError::DatabaseError)?
-                .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
-            random_identities.push(peer);
+    /// Returns true if the peer has at least one address that can be dialed
+    pub fn has_addresses(&self, node_id: &NodeId) -> Result<bool, PeerManagerError> {
+        self.find_by_node_id(node_id).map(|peer| !peer.addresses.is_empty())
+    }
+
+    /// Returns true if at least one connectable peer (see `Peer::is_connectable`) with the given features exists. The
+    /// scan stops at the first eligible peer.
+    pub fn any_connectable(&self, features: Option<PeerFeatures>) -> Result<bool, PeerManagerError> {
+        self.has_min_connectable(features, 1)
+    }
+
+    /// Returns true if at least `min` peers with the given features (or any features if None) are connectable. The
+    /// scan stops as soon as `min` connectable peers have been found.
+    pub fn has_min_connectable(&self, features: Option<PeerFeatures>, min: usize) -> Result<bool, PeerManagerError> {
+        if min == 0 {
+            return Ok(true);
         }
-        Ok(random_identities)
+        let mut num_found = 0;
+        self.peer_db
+            .for_each_ok(|(_, peer)| {
+                if features.map(|f| peer.features == f).unwrap_or(true) && peer.is_connectable(self.offline_expiry) {
+                    num_found += 1;
+                    if num_found == min {
+                        return IterationResult::Break;
+                    }
+                }
+                IterationResult::Continue
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+        Ok(num_found >= min)
+    }
+
+    /// Count the total, online, offline and banned peers for each distinct set of peer features in a single scan of
+    /// the store
+    pub fn feature_breakdown(&self) -> Result<HashMap<PeerFeatures, FeatureCounts>, PeerManagerError> {
+        let mut breakdown = HashMap::<_, FeatureCounts>::new();
+        self.for_each(|peer| {
+            let counts = breakdown.entry(peer.features).or_default();
+            counts.total += 1;
+            if peer.is_offline() {
+                counts.offline += 1;
+            } else {
+                counts.online += 1;
+            }
+            if peer.is_banned() {
+                counts.banned += 1;
+            }
+            IterationResult::Continue
+        })?;
+        Ok(breakdown)
     }
 
     /// Check if a specific node_id is in the network region of the N nearest neighbours of the region specified by
@@ -397,7 +784,7 @@ where DS: KeyValueStore<PeerId, Peer>
         n: usize,
     ) -> Result<bool, PeerManagerError>
     {
-        let region_node_distance = region_node_id.distance(node_id);
+        let region_node_distance = self.distance_metric.distance(region_node_id, node_id);
         let node_threshold = self.calc_region_threshold(region_node_id, n, PeerFeatures::COMMUNICATION_NODE)?;
         // Is node ID in the base node threshold?
         if region_node_distance <= node_threshold {
@@ -434,9 +821,7 @@ where DS: KeyValueStore<PeerId, Peer>
 
         if peer.banned_until.is_some() {
             peer.unban();
-            self.peer_db
-                .insert(peer_key, peer)
-                .map_err(PeerManagerError::DatabaseError)?;
+            self.put_peer(peer_key, peer)?;
         }
         Ok(node_id)
     }
@@ -454,12 +839,32 @@ where DS: KeyValueStore<PeerId, Peer>
             .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
         peer.ban_for(duration);
         let node_id = peer.node_id.clone();
-        self.peer_db
-            .insert(peer_key, peer)
-            .map_err(PeerManagerError::DatabaseError)?;
+        self.put_peer(peer_key, peer)?;
         Ok(node_id)
     }
 
+    /// Ban every peer that has an IP address within the given subnet for the given duration. The node ids of the
+    /// banned peers are returned.
+    pub fn ban_subnet(&mut self, subnet: &IpCidr, duration: Duration) -> Result<Vec<NodeId>, PeerManagerError> {
+        let peers_in_subnet = self
+            .peer_db
+            .filter(|(_, peer)| {
+                peer.addresses
+                    .address_iter()
+                    .filter_map(multiaddr_to_ip)
+                    .any(|ip| subnet.contains(&ip))
+            })
+            .map_err(PeerManagerError::DatabaseError)?;
+
+        let mut node_ids = Vec::with_capacity(peers_in_subnet.len());
+        for (peer_key, mut peer) in peers_in_subnet {
+            peer.ban_for(duration);
+            node_ids.push(peer.node_id.clone());
+            self.put_peer(peer_key, peer)?;
+        }
+        Ok(node_ids)
+    }
+
     /// Changes the OFFLINE flag bit of the peer
     pub fn set_offline(&mut self, public_key: &CommsPublicKey, ban_flag: bool) -> Result<NodeId, PeerManagerError> {
         let peer_key = *self
@@ -473,9 +878,7 @@ where DS: KeyValueStore<PeerId, Peer>
             .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
         peer.set_offline(ban_flag);
         let node_id = peer.node_id.clone();
-        self.peer_db
-            .insert(peer_key, peer)
-            .map_err(PeerManagerError::DatabaseError)?;
+        self.put_peer(peer_key, peer)?;
         Ok(node_id)
     }
 
@@ -491,9 +894,7 @@ where DS: KeyValueStore<PeerId, Peer>
             .map_err(PeerManagerError::DatabaseError)?
             .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
         peer.addresses.add_net_address(net_address);
-        self.peer_db
-            .insert(peer_key, peer)
-            .map_err(PeerManagerError::DatabaseError)
+        self.put_peer(peer_key, peer)
     }
 
     /// Return some basic stats for the region surrounding the region_node_id. The size of the local region is
@@ -509,20 +910,24 @@ where DS: KeyValueStore<PeerId, Peer>
         let mut valid_dists = Vec::new();
         let mut banned_dists = Vec::new();
         let mut offline_dists = Vec::new();
+        let mut success_rate_dists = Vec::new();
         self.peer_db
             .for_each_ok(|(peer_key, peer)| {
                 if peer.features != features {
                     return IterationResult::Continue;
                 }
-                let curr_dist = region_node_id.distance(&peer.node_id);
-                if !peer.is_banned() && !peer.is_offline() {
+                let curr_dist = self.distance_metric.distance(region_node_id, &peer.node_id);
+                if !peer.is_banned() && !peer.is_offline_for_selection(self.offline_expiry) {
+                    if peer.connection_stats.num_recent_attempts() > 0 {
+                        success_rate_dists.push((curr_dist.clone(), peer.connection_stats.success_rate()));
+                    }
                     valid_dists.push(curr_dist);
                     peer_keys.push(peer_key);
                 } else {
                     if peer.is_banned() {
                         banned_dists.push(curr_dist.clone());
                     }
-                    if peer.is_offline() {
+                    if peer.is_offline_for_selection(self.offline_expiry) {
                         offline_dists.push(curr_dist);
                     }
                 }
@@ -549,40 +954,95 @@ where DS: KeyValueStore<PeerId, Peer>
                     .ok_or_else(|| PeerManagerError::PeerNotFoundError)?;
                 neighbours.push(peer);
             }
-            region_node_id.distance(&neighbours.last().expect("Neighbours must be more than 0").node_id)
+            let furthest_neighbour = neighbours.last().expect("Neighbours must be more than 0");
+            self.distance_metric
+                .distance(region_node_id, &furthest_neighbour.node_id)
         } else {
             NodeDistance::max_distance()
         };
 
         let num_offline = offline_dists.into_iter().filter(|d| *d <= distance).count();
         let num_banned = banned_dists.into_iter().filter(|d| *d <= distance).count();
+        let success_rates = success_rate_dists
+            .into_iter()
+            .filter(|(d, _)| *d <= distance)
+            .map(|(_, rate)| rate)
+            .collect::<Vec<_>>();
+        let average_success_rate = if success_rates.is_empty() {
+            0.0
+        } else {
+            success_rates.iter().sum::<f32>() / success_rates.len() as f32
+        };
         Ok(RegionStats {
             distance,
+            distance_metric: self.distance_metric.clone(),
             ref_node_id: region_node_id,
             total,
             num_offline,
             num_banned,
+            average_success_rate,
         })
     }
 }
 
+/// Returns the first address of the subnet, with the given prefix length, of the peer's first IP address. The prefix
+/// length is capped to the length of the address.
+fn peer_subnet(peer: &Peer, prefix_len: u8) -> Option<IpAddr> {
+    let ip = peer.addresses.address_iter().filter_map(multiaddr_to_ip).next()?;
+    let max_prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+    IpInet::new(ip, cmp::min(prefix_len, max_prefix_len))
+        .ok()
+        .map(|inet| inet.network().first_address())
+}
+
+/// Returns the number of entries in `a` that are not in `b`
+fn count_differing_entries<K: Hash + Eq>(a: &HashMap<K, PeerId>, b: &HashMap<K, PeerId>) -> usize {
+    a.iter().filter(|(k, v)| b.get(k) != Some(v)).count()
+}
+
 impl Into<CommsDatabase> for PeerStorage<CommsDatabase> {
     fn into(self) -> CommsDatabase {
         self.peer_db
     }
 }
 
+/// The result of compacting the peer storage indexes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompactionReport {
+    /// The number of peers in the datastore
+    pub num_peers: usize,
+    /// The number of index entries that were removed because they did not match a stored peer
+    pub stale_entries_removed: usize,
+    /// The number of index entries that were added for stored peers that were not indexed
+    pub missing_entries_added: usize,
+}
+
+/// Peer counts for a set of peer features, as returned by `PeerStorage::feature_breakdown`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeatureCounts {
+    /// The number of peers with these features
+    pub total: usize,
+    /// The number of peers that are not marked as offline
+    pub online: usize,
+    /// The number of peers that are marked as offline
+    pub offline: usize,
+    /// The number of banned peers. Banned peers are also counted as online or offline.
+    pub banned: usize,
+}
+
 pub struct RegionStats<'a> {
     distance: NodeDistance,
+    distance_metric: Arc<dyn DistanceMetric>,
     ref_node_id: &'a NodeId,
     total: usize,
     num_offline: usize,
     num_banned: usize,
+    average_success_rate: f32,
 }
 
 impl RegionStats<'_> {
     pub fn in_region(&self, node_id: &NodeId) -> bool {
-        node_id.distance(self.ref_node_id) <= self.distance
+        self.distance_metric.distance(self.ref_node_id, node_id) <= self.distance
     }
 
     pub fn offline_ratio(&self) -> f32 {
@@ -592,14 +1052,20 @@ impl RegionStats<'_> {
     pub fn banned_ratio(&self) -> f32 {
         self.num_banned as f32 / self.total as f32
     }
+
+    /// Returns the average connection success rate of the online, unbanned peers in the region. Peers without any
+    /// recent connection attempts are not included. 0.0 is returned if no peer in the region has a success rate.
+    pub fn average_success_rate(&self) -> f32 {
+        self.average_success_rate
+    }
 }
 
 impl fmt::Display for RegionStats<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "RegionStats(distance = {}, total = {}, num offline = {}, num banned = {})",
-            self.distance, self.total, self.num_offline, self.num_banned
+            "RegionStats(distance = {}, total = {}, num offline = {}, num banned = {}, average success rate = {:.2})",
+            self.distance, self.total, self.num_offline, self.num_banned, self.average_success_rate
         )
     }
 }
@@ -608,9 +1074,11 @@ impl fmt::Display for RegionStats<'_> {
 mod test {
     use super::*;
     use crate::{
+        consts::PEER_DISCONNECT_QUARANTINE_THRESHOLD,
         net_address::MultiaddressesWithStats,
         peer_manager::{peer::PeerFlags, PeerFeatures},
     };
+    use chrono::TimeZone;
     use tari_crypto::{keys::PublicKey, ristretto::RistrettoPublicKey};
     use tari_storage::HashmapDatabase;
 
@@ -849,6 +1317,76 @@ mod test {
         peer
     }
 
+    #[test]
+    fn random_peers_prefers_peers_in_window() {
+        let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
+        let mut morning_peers = Vec::new();
+        let mut night_peers = Vec::new();
+        for _ in 0..2 {
+            let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+            peer.preferred_window = Some((8, 12));
+            morning_peers.push(peer.node_id.clone());
+            peer_storage.add_peer(peer).unwrap();
+            let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+            peer.preferred_window = Some((20, 4));
+            night_peers.push(peer.node_id.clone());
+            peer_storage.add_peer(peer).unwrap();
+        }
+        let anytime_peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        let anytime_node_id = anytime_peer.node_id.clone();
+        peer_storage.add_peer(anytime_peer).unwrap();
+
+        let morning = Utc.ymd(2020, 1, 1).and_hms(10, 0, 0);
+        for _ in 0..10 {
+            let peers = peer_storage.random_peers_at(3, vec![], morning).unwrap();
+            assert_eq!(peers.len(), 3);
+            assert!(peers
+                .iter()
+                .all(|p| morning_peers.contains(&p.node_id) || p.node_id == anytime_node_id));
+        }
+
+        let night = Utc.ymd(2020, 1, 1).and_hms(23, 0, 0);
+        for _ in 0..10 {
+            let peers = peer_storage.random_peers_at(3, vec![], night).unwrap();
+            assert!(peers
+                .iter()
+                .all(|p| night_peers.contains(&p.node_id) || p.node_id == anytime_node_id));
+        }
+
+        // Peers outside of their window are selected if there are not enough peers within theirs
+        let peers = peer_storage.random_peers_at(5, vec![], night).unwrap();
+        assert_eq!(peers.len(), 5);
+        assert!(peers[..3]
+            .iter()
+            .all(|p| night_peers.contains(&p.node_id) || p.node_id == anytime_node_id));
+    }
+
+    #[test]
+    fn random_peers_penalises_disconnecting_peers() {
+        let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
+        let stable_peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        let stable_node_id = stable_peer.node_id.clone();
+        peer_storage.add_peer(stable_peer).unwrap();
+        let mut disconnecting_peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        for _ in 0..PEER_DISCONNECT_QUARANTINE_THRESHOLD - 1 {
+            disconnecting_peer.connection_stats.record_peer_initiated_disconnect();
+        }
+        assert!(!disconnecting_peer.is_quarantined());
+        let disconnecting_node_id = disconnecting_peer.node_id.clone();
+        peer_storage.add_peer(disconnecting_peer).unwrap();
+
+        for _ in 0..10 {
+            let peers = peer_storage.random_peers(1, vec![]).unwrap();
+            assert_eq!(peers.len(), 1);
+            assert_eq!(peers[0].node_id, stable_node_id);
+        }
+
+        // The disconnecting peer is still selected if there are not enough other peers
+        let peers = peer_storage.random_peers(2, vec![]).unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[1].node_id, disconnecting_node_id);
+    }
+
     #[test]
     fn test_get_region_stats() {
         let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
@@ -925,4 +1463,147 @@ mod test {
         assert!(client_region_stats.distance < NodeDistance::max_distance());
         assert_eq!(client_region_stats.total, 4);
     }
+
+    #[test]
+    fn region_stats_average_success_rate() {
+        let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
+
+        let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        peer.connection_stats.set_connection_success();
+        peer.connection_stats.set_connection_failed();
+        peer_storage.add_peer(peer).unwrap();
+        let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        peer.connection_stats.set_connection_success();
+        peer_storage.add_peer(peer).unwrap();
+        // Peers without connection attempts are not included
+        peer_storage
+            .add_peer(create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false))
+            .unwrap();
+        // Offline and banned peers are not included
+        let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, true);
+        peer.connection_stats.set_connection_failed();
+        peer_storage.add_peer(peer).unwrap();
+        let mut peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, true, false);
+        peer.connection_stats.set_connection_failed();
+        peer_storage.add_peer(peer).unwrap();
+
+        let main_peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        let region_stats = peer_storage
+            .get_region_stats(&main_peer.node_id, 10, PeerFeatures::COMMUNICATION_NODE)
+            .unwrap();
+        assert_eq!(region_stats.total, 3);
+        assert!((region_stats.average_success_rate() - 0.75).abs() < std::f32::EPSILON);
+
+        let region_stats = peer_storage
+            .get_region_stats(&main_peer.node_id, 10, PeerFeatures::COMMUNICATION_CLIENT)
+            .unwrap();
+        assert!(region_stats.average_success_rate().abs() < std::f32::EPSILON);
+    }
+
+    #[test]
+    fn compact() {
+        let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
+        let peer1 = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        let peer2 = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        let unknown_peer = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false);
+        peer_storage.add_peer(peer1.clone()).unwrap();
+        peer_storage.add_peer(peer2.clone()).unwrap();
+
+        let report = peer_storage.compact().unwrap();
+        assert_eq!(report, CompactionReport {
+            num_peers: 2,
+            stale_entries_removed: 0,
+            missing_entries_added: 0,
+        });
+
+        // Corrupt the indexes
+        let peer2_key = peer_storage.node_id_index.remove(&peer2.node_id).unwrap();
+        peer_storage
+            .public_key_index
+            .insert(unknown_peer.public_key.clone(), peer2_key);
+        let peer1_key = *peer_storage.node_id_index.get(&peer1.node_id).unwrap();
+        peer_storage
+            .public_key_index
+            .insert(peer1.public_key.clone(), peer1_key + 1000);
+        assert!(peer_storage.find_by_node_id(&peer2.node_id).is_err());
+        assert!(peer_storage.find_by_public_key(&peer1.public_key).is_err());
+
+        let report = peer_storage.compact().unwrap();
+        assert_eq!(report, CompactionReport {
+            num_peers: 2,
+            stale_entries_removed: 2,
+            missing_entries_added: 2,
+        });
+        assert_eq!(
+            peer_storage.find_by_node_id(&peer2.node_id).unwrap().public_key,
+            peer2.public_key
+        );
+        assert_eq!(
+            peer_storage.find_by_public_key(&peer1.public_key).unwrap().node_id,
+            peer1.node_id
+        );
+        assert!(peer_storage.find_by_public_key(&unknown_peer.public_key).is_err());
+        assert_eq!(peer_storage.public_key_index.len(), 2);
+        assert_eq!(peer_storage.node_id_index.len(), 2);
+    }
+
+    #[test]
+    fn closest_peers_matches_naive_scan() {
+        let mut peer_storage = PeerStorage::new_indexed(HashmapDatabase::new()).unwrap();
+        let mut rng = rand::rngs::OsRng;
+        for _ in 0..3000 {
+            let features = if rng.gen_bool(0.5) {
+                PeerFeatures::COMMUNICATION_NODE
+            } else {
+                PeerFeatures::COMMUNICATION_CLIENT
+            };
+            let peer = create_test_peer(features, rng.gen_ratio(1, 10), rng.gen_ratio(1, 10));
+            peer_storage.add_peer(peer).unwrap();
+        }
+        let excluded_peers = peer_storage
+            .all()
+            .unwrap()
+            .into_iter()
+            .take(100)
+            .map(|p| p.public_key)
+            .collect::<Vec<_>>();
+
+        // Select the n closest peers by fetching and sorting every peer
+        let naive_closest_peers = |node_id: &NodeId, n: usize, features: Option<PeerFeatures>| {
+            let mut peers = peer_storage
+                .all()
+                .unwrap()
+                .into_iter()
+                .filter(|p| {
+                    features.map(|f| p.features == f).unwrap_or(true) &&
+                        !p.is_banned() &&
+                        !p.is_offline() &&
+                        !excluded_peers.contains(&p.public_key)
+                })
+                .collect::<Vec<_>>();
+            peers.sort_by(|a, b| node_id.distance(&a.node_id).cmp(&node_id.distance(&b.node_id)));
+            peers.truncate(n);
+            peers
+        };
+
+        for _ in 0..5 {
+            let node_id = create_test_peer(PeerFeatures::COMMUNICATION_NODE, false, false).node_id;
+            for &n in &[0, 1, 8, 50, 5000] {
+                for &features in &[
+                    None,
+                    Some(PeerFeatures::COMMUNICATION_NODE),
+                    Some(PeerFeatures::COMMUNICATION_CLIENT),
+                ] {
+                    let closest = peer_storage
+                        .closest_peers(&node_id, n, &excluded_peers, features)
+                        .unwrap();
+                    let expected = naive_closest_peers(&node_id, n, features);
+                    assert_eq!(
+                        closest.iter().map(|p| &p.node_id).collect::<Vec<_>>(),
+                        expected.iter().map(|p| &p.node_id).collect::<Vec<_>>()
+                    );
+                }
+            }
+        }
+    }
 }
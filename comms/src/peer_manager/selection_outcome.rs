@@ -0,0 +1,55 @@
+// Copyright 2020, The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::peer_manager::Peer;
+
+/// The outcome of a peer selection that distinguishes why fewer peers than requested were selected, as returned by
+/// `PeerManager::closest_peers_outcome`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionOutcome {
+    /// The requested number of peers were selected
+    Complete(Vec<Peer>),
+    /// Some, but fewer than the requested number of peers were selected
+    Insufficient(Vec<Peer>),
+    /// The peer store has peers, but none of them are eligible for selection
+    NoEligiblePeers,
+    /// The peer store has no peers
+    NoPeers,
+}
+
+impl SelectionOutcome {
+    /// Returns true if the requested number of peers were selected
+    pub fn is_complete(&self) -> bool {
+        match self {
+            SelectionOutcome::Complete(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the selected peers, which is empty if no peers were selected
+    pub fn into_peers(self) -> Vec<Peer> {
+        match self {
+            SelectionOutcome::Complete(peers) | SelectionOutcome::Insufficient(peers) => peers,
+            SelectionOutcome::NoEligiblePeers | SelectionOutcome::NoPeers => Vec::new(),
+        }
+    }
+}
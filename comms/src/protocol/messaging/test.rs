@@ -112,8 +112,8 @@ async fn new_inbound_substream_handling() {
     let (peer_manager, _, _, mut proto_tx, _, mut inbound_msg_rx, mut events_rx, _shutdown) =
         spawn_messaging_protocol().await;
 
-    let expected_node_id = node_id::random();
     let (_, pk) = CommsPublicKey::random_keypair(&mut OsRng);
+    let expected_node_id = NodeId::from_key(&pk).unwrap();
     peer_manager
         .add_peer(Peer::new(
             pk.clone(),
@@ -37,3 +37,13 @@ pub mod messaging;
 /// Represents a protocol id string (e.g. /tari/transactions/1.0.0).
 /// This is atomically reference counted, so clones are shallow and cheap
 pub type ProtocolId = bytes::Bytes;
+
+/// Splits a protocol id into its family and major version (e.g. `/tari/transactions/1.0.0` is split into
+/// `/tari/transactions` and `1`). None is returned if the protocol id does not end in a numeric version.
+pub fn split_protocol_id(protocol_id: &ProtocolId) -> Option<(&[u8], u32)> {
+    let pos = protocol_id.iter().rposition(|b| *b == b'/')?;
+    let (family, version) = (&protocol_id[..pos], &protocol_id[pos + 1..]);
+    let major = version.split(|b| *b == b'.').next()?;
+    let major = std::str::from_utf8(major).ok()?.parse().ok()?;
+    Some((family, major))
+}
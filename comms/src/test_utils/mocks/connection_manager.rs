@@ -137,7 +137,7 @@ impl ConnectionManagerMock {
                             .await
                             .get(&node_id)
                             .map(Clone::clone)
-                            .ok_or_else(|| ConnectionManagerError::DialConnectFailedAllAddresses),
+                            .ok_or_else(|| ConnectionManagerError::DialConnectFailedAllAddresses(None)),
                     )
                     .unwrap();
             },
@@ -33,7 +33,6 @@ use futures::channel::mpsc;
 use rand::rngs::OsRng;
 use std::{sync::Arc, time::Duration};
 use tari_shutdown::ShutdownSignal;
-use tari_storage::HashmapDatabase;
 use tokio::sync::broadcast;
 
 #[derive(Clone, Debug)]
@@ -99,5 +98,5 @@ pub fn build_connection_manager(
 }
 
 pub fn build_peer_manager() -> Arc<PeerManager> {
-    Arc::new(PeerManager::new(HashmapDatabase::new()).unwrap())
+    Arc::new(PeerManager::new_in_memory().unwrap())
 }
@@ -43,7 +43,7 @@ pub trait Transport {
     /// The output of the transport after a connection is established
     type Output;
     /// Transport error type
-    type Error: std::error::Error + Send + Sync;
+    type Error: std::error::Error + Send + Sync + 'static;
     /// A future which resolves to `Self::Output`
     type Inbound: Future<Output = Result<Self::Output, Self::Error>> + Send;
     /// A stream which emits `Self::InboundFuture` whenever a successful inbound connection is made
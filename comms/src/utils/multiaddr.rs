@@ -68,6 +68,16 @@ pub fn multiaddr_to_socketaddr(addr: &Multiaddr) -> io::Result<SocketAddr> {
     }
 }
 
+/// Returns the IP address of an `/ip4` or `/ip6` multiaddr, or None if the address does not contain an IP address.
+/// Unlike `multiaddr_to_socketaddr`, DNS addresses are not resolved.
+pub fn multiaddr_to_ip(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(ip.into()),
+        Protocol::Ip6(ip) => Some(ip.into()),
+        _ => None,
+    })
+}
+
 /// Convert a socket address to a multiaddress. Assumes the protocol is Tcp
 pub fn socketaddr_to_multiaddr(socket_addr: &SocketAddr) -> Multiaddr {
     let mut addr: Multiaddr = match socket_addr.ip() {
@@ -97,6 +107,16 @@ mod test {
         expect_success("/dns4/taridns.dyn-ip.me/tcp/1234", "127.0.0.1");
     }
 
+    #[test]
+    fn multiaddr_to_ip() {
+        let addr = Multiaddr::from_str("/ip4/254.0.1.2/tcp/1234").unwrap();
+        assert_eq!(super::multiaddr_to_ip(&addr).unwrap().to_string(), "254.0.1.2");
+        let addr = Multiaddr::from_str("/ip6/::1/tcp/1234").unwrap();
+        assert_eq!(super::multiaddr_to_ip(&addr).unwrap().to_string(), "::1");
+        let addr = Multiaddr::from_str("/dns4/taridns.dyn-ip.me/tcp/1234").unwrap();
+        assert!(super::multiaddr_to_ip(&addr).is_none());
+    }
+
     #[test]
     fn multiaddr_dns_to_socketaddr_ok() {
         let addr = Multiaddr::from_str("/dns4/localhost/tcp/1234").unwrap();